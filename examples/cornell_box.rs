@@ -0,0 +1,77 @@
+//
+// cornell_box.rs
+//
+
+use glam::{Mat4, Vec3};
+use natsukashii::{
+    geometry::Centered,
+    light::{Light, Shadow},
+    mesh::{Index, Mesh, Vertex},
+    model::Model,
+    scene::Scene,
+    Engine, EngineConfig, EngineParams,
+};
+use std::error::Error;
+
+#[allow(dead_code)]
+fn demo_mesh() -> Mesh {
+    use genmesh::{
+        generators::{IcoSphere, IndexedPolygon, SharedVertex},
+        Triangulate,
+    };
+
+    let prim = IcoSphere::subdivide(1);
+    let vertices: Vec<Vertex> = prim
+        .shared_vertex_iter()
+        .map(|v| Vertex::new(v.pos.into()))
+        .collect();
+    let indices: Vec<Index> = prim
+        .indexed_polygon_iter()
+        .triangulate()
+        .map(|f| vec![f.x as _, f.y as _, f.z as _])
+        .flatten()
+        .collect();
+
+    let mut mesh = Mesh { vertices, indices };
+    mesh.generate_normals();
+    mesh
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    // Initialize logging
+    let log_env = env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info");
+    env_logger::init_from_env(log_env);
+    log::info!("Hello there!");
+
+    // Prepare the engine params, allowing overrides from the environment
+    let params = EngineParams::default()
+        .with_size((1280, 720))
+        .with_title("Cornell box")
+        .with_config(&EngineConfig::from_env()?)?;
+
+    // Create the engine
+    let mut engine = futures::executor::block_on(Engine::new(&params))?;
+
+    // Create cornell box
+    let mut model = Model::cornell_box();
+    model.meshes = model.meshes.centered();
+
+    // Create demo scene
+    let cpos = (0.0, 0.0, -3.5).into();
+    let view = Mat4::look_at_lh(cpos, Vec3::ZERO, Vec3::Y);
+    let mut scene = Scene {
+        view,
+        ..Default::default()
+    };
+    scene.add_model(&model, Mat4::IDENTITY);
+    let light = Light::point(Vec3::new(0.0, 0.9, 0.0), 4.0)
+        .with_color(Vec3::ONE, 2.0)
+        .with_shadow(Shadow::default());
+    scene.lights.push(light);
+    engine.set_scene(scene);
+    engine.set_camera_position(cpos);
+
+    // Run
+    engine.run(());
+    Ok(())
+}
@@ -1,18 +1,95 @@
 #version 450
+#extension GL_GOOGLE_include_directive : require
+#include <lights>
+#include <brdf>
+
 layout(location = 0) in vec3 vpos;
 layout(location = 1) in vec3 vnrm;
+layout(location = 2) in vec2 vuv;
 
 layout(location = 0) out vec4 fcolor;
 
+layout(std140, set = 0, binding = 0)
+uniform ViewProj {
+    mat4 view;
+    mat4 proj;
+    vec4 eye;
+};
+
 layout(std140, set = 2, binding = 0)
 uniform Material {
-    vec3 alb;
+    vec3 base_color;
+    float metallic;
+    vec3 emissive;
+    float roughness;
+    vec3 specular;
+    float shininess;
+    vec3 ambient;
+    float dissolve;
+    float normal_scale;
+    float occlusion_strength;
+    uint shading;
 };
 
+#define SHADING_METALLIC_ROUGHNESS 0
+#define SHADING_BLINN_PHONG 1
+
+layout(set = 2, binding = 1) uniform sampler map_sampler;
+layout(set = 2, binding = 2) uniform texture2D base_color_map;
+layout(set = 2, binding = 3) uniform texture2D metallic_roughness_map;
+layout(set = 2, binding = 4) uniform texture2D normal_map;
+layout(set = 2, binding = 5) uniform texture2D occlusion_map;
+layout(set = 2, binding = 6) uniform texture2D emissive_map;
+
+// Constant ambient light, the only light attenuated by the occlusion map.
+// Blinn-Phong materials reflect it with their ambient color
+const vec3 AMBIENT = vec3(0.03);
+
+// Applies the normal map in a tangent frame derived from screen space derivatives
+vec3 perturb_normal(vec3 n)
+{
+    vec3 dp1 = dFdx(vpos);
+    vec3 dp2 = dFdy(vpos);
+    vec2 duv1 = dFdx(vuv);
+    vec2 duv2 = dFdy(vuv);
+    vec3 dp2perp = cross(dp2, n);
+    vec3 dp1perp = cross(n, dp1);
+    vec3 t = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 b = dp2perp * duv1.y + dp1perp * duv2.y;
+    float scale = inversesqrt(max(max(dot(t, t), dot(b, b)), 1e-12));
+
+    // Texture rows go down the image while the green channel points up
+    vec3 tnrm = texture(sampler2D(normal_map, map_sampler), vuv).xyz * 2.0 - 1.0;
+    tnrm.xy *= normal_scale;
+    return normalize(mat3(t * scale, -b * scale, n) * tnrm);
+}
+
 void main()
 {
     vec3 nrm = normalize(vnrm);
-    vec3 dir = normalize(vec3(0.0, 1.0, 0.0) - vpos);
-    vec3 col = alb * vec3(max(dot(nrm, dir), 0.0));
-    fcolor = vec4(col, 1.0);
+    if (normal_scale > 0.0) {
+        nrm = perturb_normal(nrm);
+    }
+    vec3 v = normalize(eye.xyz - vpos);
+
+    vec3 albedo = base_color * texture(sampler2D(base_color_map, map_sampler), vuv).rgb;
+    vec2 mr = texture(sampler2D(metallic_roughness_map, map_sampler), vuv).bg;
+    float metal = metallic * mr.x;
+    float rough = clamp(roughness * mr.y, 0.04, 1.0);
+    float ao = texture(sampler2D(occlusion_map, map_sampler), vuv).r;
+    ao = mix(1.0, ao, occlusion_strength);
+    vec3 emit = emissive * texture(sampler2D(emissive_map, map_sampler), vuv).rgb;
+
+    vec3 amb = shading == SHADING_BLINN_PHONG ? ambient : albedo;
+    vec3 col = AMBIENT * amb * ao + emit;
+    for (uint i = 0; i < min(nlights, uint(MAX_LIGHTS)); ++i) {
+        vec3 ldir;
+        vec3 radiance = light_radiance(lights[i], vpos, ldir);
+        float ndotl = max(dot(nrm, ldir), 0.0);
+        vec3 f = shading == SHADING_BLINN_PHONG
+            ? blinn_phong(nrm, v, ldir, albedo, specular, shininess)
+            : brdf(nrm, v, ldir, albedo, metal, rough);
+        col += f * radiance * ndotl * light_shadow(lights[i], vpos, ndotl);
+    }
+    fcolor = vec4(col, dissolve);
 }
@@ -1,14 +1,17 @@
 #version 450
 layout(location = 0) in vec3 apos;
 layout(location = 1) in vec3 anrm;
+layout(location = 2) in vec2 auv;
 
 layout(location = 0) out vec3 vpos;
 layout(location = 1) out vec3 vnrm;
+layout(location = 2) out vec2 vuv;
 
 layout(std140, set = 0, binding = 0)
 uniform ViewProj {
     mat4 view;
     mat4 proj;
+    vec4 eye;
 };
 
 layout(std140, set = 1, binding = 0)
@@ -20,5 +23,6 @@ void main()
 {
     vpos = (model * vec4(apos, 1.0)).xyz;
     vnrm = normalize((model * vec4(anrm, 0.0)).xyz);
+    vuv = auv;
     gl_Position = proj * view * model * vec4(apos, 1.0);
 }
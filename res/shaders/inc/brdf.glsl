@@ -0,0 +1,53 @@
+#define PI 3.14159265359
+
+// GGX / Trowbridge-Reitz normal distribution
+float distribution_ggx(float ndoth, float roughness)
+{
+    float a2 = roughness * roughness * roughness * roughness;
+    float d = ndoth * ndoth * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Smith shadowing-masking with the Schlick-GGX approximation for direct lighting
+float geometry_smith(float ndotv, float ndotl, float roughness)
+{
+    float r = roughness + 1.0;
+    float k = r * r / 8.0;
+    float gv = ndotv / (ndotv * (1.0 - k) + k);
+    float gl = ndotl / (ndotl * (1.0 - k) + k);
+    return gv * gl;
+}
+
+vec3 fresnel_schlick(float cosa, vec3 f0)
+{
+    return f0 + (1.0 - f0) * pow(1.0 - cosa, 5.0);
+}
+
+// Cook-Torrance BRDF with a Lambert diffuse lobe, n, v and l pointing away from the surface
+vec3 brdf(vec3 n, vec3 v, vec3 l, vec3 albedo, float metallic, float roughness)
+{
+    vec3 h = normalize(v + l);
+    float ndotv = max(dot(n, v), 1e-4);
+    float ndotl = max(dot(n, l), 0.0);
+    float ndoth = max(dot(n, h), 0.0);
+    float hdotv = max(dot(h, v), 0.0);
+
+    // Dielectrics reflect 4% at normal incidence, metals tint it with their albedo
+    vec3 f0 = mix(vec3(0.04), albedo, metallic);
+    vec3 f = fresnel_schlick(hdotv, f0);
+    float d = distribution_ggx(ndoth, roughness);
+    float g = geometry_smith(ndotv, ndotl, roughness);
+    vec3 specular = d * g * f / max(4.0 * ndotv * ndotl, 1e-4);
+
+    vec3 kd = (1.0 - f) * (1.0 - metallic);
+    return kd * albedo / PI + specular;
+}
+
+// Energy normalized Blinn-Phong, n, v and l pointing away from the surface
+vec3 blinn_phong(vec3 n, vec3 v, vec3 l, vec3 albedo, vec3 specular, float shininess)
+{
+    vec3 h = normalize(v + l);
+    float ndoth = max(dot(n, h), 0.0);
+    float norm = (shininess + 8.0) / (8.0 * PI);
+    return albedo / PI + specular * norm * pow(ndoth, shininess);
+}
@@ -0,0 +1,141 @@
+#define MAX_LIGHTS 16
+#define LIGHT_DIRECTIONAL 0
+#define LIGHT_POINT 1
+#define LIGHT_SPOT 2
+
+struct Light {
+    vec4 position;  // w: kind
+    vec4 direction; // w: range
+    vec4 color;     // scaled by intensity
+    vec4 cone;      // x: cosine of inner angle, y: cosine of outer angle
+    mat4 view_proj; // world to shadow map
+    vec4 shadow;    // x: layer or -1, y: bias, z: slope bias, w: fraction of the layer used
+};
+
+layout(std140, set = 3, binding = 0)
+uniform Lights {
+    Light lights[MAX_LIGHTS];
+    uint nlights;
+};
+
+layout(set = 3, binding = 1) uniform texture2DArray shadow_maps;
+layout(set = 3, binding = 2) uniform samplerShadow shadow_sampler;
+
+// Smooth window reaching zero at range, on top of inverse square falloff
+float light_falloff(float dist, float range)
+{
+    float r = dist / range;
+    float window = clamp(1.0 - r * r * r * r, 0.0, 1.0);
+    return window * window / (dist * dist + 1.0);
+}
+
+// Radiance arriving at pos from the light, with the direction towards the light in ldir
+vec3 light_radiance(Light l, vec3 pos, out vec3 ldir)
+{
+    int kind = int(l.position.w);
+    if (kind == LIGHT_DIRECTIONAL) {
+        ldir = -normalize(l.direction.xyz);
+        return l.color.rgb;
+    }
+
+    vec3 d = l.position.xyz - pos;
+    float dist = length(d);
+    ldir = d / dist;
+    float att = light_falloff(dist, l.direction.w);
+    if (kind == LIGHT_SPOT) {
+        float cosa = dot(-ldir, normalize(l.direction.xyz));
+        att *= smoothstep(l.cone.y, l.cone.x, cosa);
+    }
+    return l.color.rgb * att;
+}
+
+// Cube face directions and up vectors, matching `CUBE_FACES` in renderer.rs
+const vec3 CUBE_DIRS[6] = vec3[6](
+    vec3(1.0, 0.0, 0.0), vec3(-1.0, 0.0, 0.0),
+    vec3(0.0, 1.0, 0.0), vec3(0.0, -1.0, 0.0),
+    vec3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0)
+);
+const vec3 CUBE_UPS[6] = vec3[6](
+    vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0),
+    vec3(0.0, 0.0, -1.0), vec3(0.0, 0.0, 1.0),
+    vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0)
+);
+
+// Compares depth against the shadow map in layer over 3x3 texels around uv
+float shadow_pcf(vec2 uv, float layer, float depth)
+{
+    vec2 texel = 1.0 / vec2(textureSize(sampler2DArrayShadow(shadow_maps, shadow_sampler), 0).xy);
+    float lit = 0.0;
+    for (int y = -1; y <= 1; ++y) {
+        for (int x = -1; x <= 1; ++x) {
+            vec4 coord = vec4(uv + vec2(x, y) * texel, layer, depth);
+            lit += texture(sampler2DArrayShadow(shadow_maps, shadow_sampler), coord);
+        }
+    }
+    return lit / 9.0;
+}
+
+// Layer offset of the cube face d points through, and the position of d on it
+vec3 cube_face_uv(vec3 d, float fraction)
+{
+    vec3 a = abs(d);
+    int face = a.x >= a.y && a.x >= a.z ? (d.x > 0.0 ? 0 : 1)
+             : a.y >= a.z ? (d.y > 0.0 ? 2 : 3)
+             : (d.z > 0.0 ? 4 : 5);
+
+    // Same basis as a left handed look at view, with a 90 degree projection
+    vec3 f = CUBE_DIRS[face];
+    vec3 s = normalize(cross(CUBE_UPS[face], f));
+    vec3 u = cross(f, s);
+    vec2 ndc = vec2(dot(d, s), dot(d, u)) / dot(d, f);
+    return vec3((ndc * vec2(0.5, -0.5) + 0.5) * fraction, float(face));
+}
+
+// Compares depth against the cube faces starting at layer over 3x3 texels around d.
+// Every sample picks its own face, so the kernel crosses face borders without seams
+float shadow_pcf_cube(vec3 d, float layer, float fraction, float depth)
+{
+    // Texel size on the plane of the face d points through
+    vec3 a = abs(d);
+    float dist = max(a.x, max(a.y, a.z));
+    float res = float(textureSize(sampler2DArrayShadow(shadow_maps, shadow_sampler), 0).x);
+    float texel = 2.0 * dist / (res * fraction);
+
+    // Any basis perpendicular to d will do
+    vec3 n = d / length(d);
+    vec3 s = normalize(cross(abs(n.y) > 0.99 ? vec3(0.0, 0.0, 1.0) : vec3(0.0, 1.0, 0.0), n));
+    vec3 u = cross(n, s);
+
+    float lit = 0.0;
+    for (int y = -1; y <= 1; ++y) {
+        for (int x = -1; x <= 1; ++x) {
+            vec3 uv = cube_face_uv(d + (float(x) * s + float(y) * u) * texel, fraction);
+            vec4 coord = vec4(uv.xy, layer + uv.z, depth);
+            lit += texture(sampler2DArrayShadow(shadow_maps, shadow_sampler), coord);
+        }
+    }
+    return lit / 9.0;
+}
+
+// Fraction of the light reaching pos past the shadow casters
+float light_shadow(Light l, vec3 pos, float ndotl)
+{
+    if (l.shadow.x < 0.0) {
+        return 1.0;
+    }
+    float bias = max(l.shadow.z * (1.0 - ndotl), l.shadow.y);
+
+    // Point lights store linear distances in six layers, one per cube face
+    if (int(l.position.w) == LIGHT_POINT) {
+        vec3 d = pos - l.position.xyz;
+        return shadow_pcf_cube(d, l.shadow.x, l.shadow.w, length(d) / l.direction.w - bias);
+    }
+
+    vec4 p = l.view_proj * vec4(pos, 1.0);
+    vec3 ndc = p.xyz / p.w;
+    if (ndc.z > 1.0) {
+        return 1.0;
+    }
+    vec2 uv = (ndc.xy * vec2(0.5, -0.5) + 0.5) * l.shadow.w;
+    return shadow_pcf(uv, l.shadow.x, ndc.z - bias);
+}
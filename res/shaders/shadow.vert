@@ -0,0 +1,23 @@
+#version 450
+layout(location = 0) in vec3 apos;
+
+layout(location = 0) out vec3 vview;
+
+layout(std140, set = 0, binding = 0)
+uniform Shadow {
+    mat4 view;
+    mat4 proj;
+    float range;
+};
+
+layout(std140, set = 1, binding = 0)
+uniform Transform {
+    mat4 model;
+};
+
+void main()
+{
+    vec4 pos = view * model * vec4(apos, 1.0);
+    vview = pos.xyz;
+    gl_Position = proj * pos;
+}
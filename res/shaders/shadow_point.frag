@@ -0,0 +1,15 @@
+#version 450
+layout(location = 0) in vec3 vview;
+
+layout(std140, set = 0, binding = 0)
+uniform Shadow {
+    mat4 view;
+    mat4 proj;
+    float range;
+};
+
+void main()
+{
+    // Light views are rigid, so the view space length is the distance to the light
+    gl_FragDepth = length(vview) / range;
+}
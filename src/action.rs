@@ -0,0 +1,342 @@
+//
+// action.rs
+//
+
+use super::input::Input;
+use gilrs::{Axis, Button};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use winit::event::{ModifiersState, MouseButton, VirtualKeyCode};
+
+/// Default turn rate of a fully deflected stick, as pixels of mouse motion per second.
+/// The controllers scale it by the step duration once
+const TURN_RATE: f32 = 600.0;
+
+/// Named actions and axes bound to physical inputs
+///
+/// Code asks for actions like `move_forward` instead of specific keys or gamepad buttons,
+/// so controls can be remapped through the `bindings` of an `EngineConfig`.
+/// Every action and axis may have any number of bindings
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ActionMap {
+    #[serde(default)]
+    pub actions: BTreeMap<String, Vec<Binding>>,
+    #[serde(default)]
+    pub axes: BTreeMap<String, Vec<AxisBinding>>,
+}
+
+/// Button an action is bound to
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+    /// Button of any connected gamepad
+    Gamepad(Button),
+    /// Binding active only while all the modifiers are held, e.g. `With([Ctrl], Key(S))`
+    With(Vec<Modifier>, Box<Binding>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Modifier {
+    Shift,
+    Ctrl,
+    Alt,
+    Logo,
+}
+
+/// Input an axis is bound to, the values of all bindings of an axis add up
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AxisBinding {
+    /// -1 while the first binding is held and +1 while the second one is
+    Buttons(Binding, Binding),
+    /// Horizontal mouse motion in pixels
+    MouseX,
+    /// Vertical mouse motion in pixels, positive downwards
+    MouseY,
+    /// Scroll wheel lines, positive away from the user
+    Scroll,
+    /// Gamepad axis from -1 to 1 past its dead zone, stick Y axes are positive upwards
+    GamepadAxis(Axis),
+    /// Analog gamepad button like a trigger, from 0 to 1 past its dead zone
+    GamepadButton(Button),
+    /// Another binding multiplied by a factor, e.g. `Scaled(-1.0, MouseY)` to invert it
+    Scaled(f32, Box<AxisBinding>),
+}
+
+/// Actions of an ActionMap evaluated against the current Input
+#[derive(Copy, Clone)]
+pub struct ActionState<'a> {
+    pub map: &'a ActionMap,
+    pub input: &'a Input,
+}
+
+impl ActionMap {
+    /// Map without any bindings
+    pub fn new() -> Self {
+        ActionMap {
+            actions: BTreeMap::new(),
+            axes: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a binding to `action`
+    pub fn bind(&mut self, action: &str, binding: Binding) {
+        self.actions
+            .entry(action.to_owned())
+            .or_default()
+            .push(binding);
+    }
+
+    /// Adds a binding to `axis`
+    pub fn bind_axis(&mut self, axis: &str, binding: AxisBinding) {
+        self.axes.entry(axis.to_owned()).or_default().push(binding);
+    }
+
+    pub fn with_binding(mut self, action: &str, binding: Binding) -> Self {
+        self.bind(action, binding);
+        self
+    }
+
+    pub fn with_axis_binding(mut self, axis: &str, binding: AxisBinding) -> Self {
+        self.bind_axis(axis, binding);
+        self
+    }
+
+    /// Replaces the bindings of every action and axis present in `other`
+    pub fn extend(&mut self, other: &ActionMap) {
+        self.actions.extend(other.actions.clone());
+        self.axes.extend(other.axes.clone());
+    }
+
+    /// Evaluates the actions against `input`
+    pub fn state<'a>(&'a self, input: &'a Input) -> ActionState<'a> {
+        ActionState { map: self, input }
+    }
+}
+
+/// Default controls, WASD with mouse look for flying and mouse buttons for orbiting.
+/// Gamepads move with the left stick and turn with the right one
+impl Default for ActionMap {
+    fn default() -> Self {
+        use AxisBinding::{GamepadAxis, Scaled};
+        use Binding::{Key, Mouse};
+        ActionMap::new()
+            .with_binding("quit", Key(VirtualKeyCode::Escape))
+            .with_binding("screenshot", Key(VirtualKeyCode::F12))
+            .with_binding("grab_cursor", Mouse(MouseButton::Left))
+            .with_binding("release_cursor", Key(VirtualKeyCode::RControl))
+            .with_binding("move_forward", Key(VirtualKeyCode::W))
+            .with_binding("move_left", Key(VirtualKeyCode::A))
+            .with_binding("move_backward", Key(VirtualKeyCode::S))
+            .with_binding("move_right", Key(VirtualKeyCode::D))
+            .with_binding("orbit_rotate", Mouse(MouseButton::Left))
+            .with_binding("orbit_pan", Mouse(MouseButton::Middle))
+            .with_axis_binding("look_x", AxisBinding::MouseX)
+            .with_axis_binding("look_y", AxisBinding::MouseY)
+            .with_axis_binding("zoom", AxisBinding::Scroll)
+            .with_axis_binding("move_x", GamepadAxis(Axis::LeftStickX))
+            .with_axis_binding("move_z", GamepadAxis(Axis::LeftStickY))
+            .with_axis_binding(
+                "turn_x",
+                Scaled(TURN_RATE, Box::new(GamepadAxis(Axis::RightStickX))),
+            )
+            .with_axis_binding(
+                "turn_y",
+                Scaled(-TURN_RATE, Box::new(GamepadAxis(Axis::RightStickY))),
+            )
+    }
+}
+
+impl<'a> ActionState<'a> {
+    /// Returns true when any binding of `action` went from "not pressed" to "pressed"
+    pub fn pressed(&self, action: &str) -> bool {
+        self.bindings(action).iter().any(|b| b.pressed(self.input))
+    }
+
+    /// Returns true when any binding of `action` went from "pressed" to "not pressed"
+    pub fn released(&self, action: &str) -> bool {
+        self.bindings(action).iter().any(|b| b.released(self.input))
+    }
+
+    /// Returns true while any binding of `action` remains "pressed"
+    pub fn held(&self, action: &str) -> bool {
+        self.bindings(action).iter().any(|b| b.held(self.input))
+    }
+
+    /// Returns the sum of the bindings of `axis` during the last step, 0 if it is unbound
+    pub fn axis(&self, axis: &str) -> f32 {
+        self.map
+            .axes
+            .get(axis)
+            .map_or(0.0, |b| b.iter().map(|b| b.value(self.input)).sum())
+    }
+
+    fn bindings(&self, action: &str) -> &'a [Binding] {
+        self.map.actions.get(action).map_or(&[], Vec::as_slice)
+    }
+}
+
+impl Binding {
+    fn pressed(&self, input: &Input) -> bool {
+        match self {
+            Binding::Key(key) => input.key_pressed(*key),
+            Binding::Mouse(button) => input.mouse_pressed(*button),
+            Binding::Gamepad(button) => input.gamepad_pressed(*button),
+            Binding::With(mods, b) => modifiers_held(mods, input) && b.pressed(input),
+        }
+    }
+
+    fn released(&self, input: &Input) -> bool {
+        match self {
+            Binding::Key(key) => input.key_released(*key),
+            Binding::Mouse(button) => input.mouse_released(*button),
+            Binding::Gamepad(button) => input.gamepad_released(*button),
+            Binding::With(_, b) => b.released(input),
+        }
+    }
+
+    fn held(&self, input: &Input) -> bool {
+        match self {
+            Binding::Key(key) => input.key_held(*key),
+            Binding::Mouse(button) => input.mouse_held(*button),
+            Binding::Gamepad(button) => input.gamepad_held(*button),
+            Binding::With(mods, b) => modifiers_held(mods, input) && b.held(input),
+        }
+    }
+}
+
+impl AxisBinding {
+    fn value(&self, input: &Input) -> f32 {
+        match self {
+            AxisBinding::Buttons(negative, positive) => {
+                positive.held(input) as i32 as f32 - negative.held(input) as i32 as f32
+            }
+            AxisBinding::MouseX => input.mouse_diff().0,
+            AxisBinding::MouseY => input.mouse_diff().1,
+            AxisBinding::Scroll => input.scroll_diff(),
+            AxisBinding::GamepadAxis(axis) => input.gamepad_axis(*axis),
+            AxisBinding::GamepadButton(button) => input.gamepad_button_value(*button),
+            AxisBinding::Scaled(factor, b) => factor * b.value(input),
+        }
+    }
+}
+
+fn modifiers_held(modifiers: &[Modifier], input: &Input) -> bool {
+    let held = input.modifiers();
+    modifiers.iter().all(|m| match m {
+        Modifier::Shift => held.contains(ModifiersState::SHIFT),
+        Modifier::Ctrl => held.contains(ModifiersState::CTRL),
+        Modifier::Alt => held.contains(ModifiersState::ALT),
+        Modifier::Logo => held.contains(ModifiersState::LOGO),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::{
+        event::{DeviceId, ElementState, Event, KeyboardInput, StartCause, WindowEvent},
+        window::WindowId,
+    };
+
+    /// Feeds the window events to `input` as a new step
+    fn step(input: &mut Input, events: Vec<WindowEvent<'static>>) {
+        input.update::<()>(&Event::NewEvents(StartCause::Poll));
+        for event in events {
+            let window_id = unsafe { WindowId::dummy() };
+            input.update::<()>(&Event::WindowEvent { window_id, event });
+        }
+    }
+
+    #[allow(deprecated)]
+    fn key(key: VirtualKeyCode, state: ElementState) -> WindowEvent<'static> {
+        WindowEvent::KeyboardInput {
+            device_id: unsafe { DeviceId::dummy() },
+            input: KeyboardInput {
+                scancode: 0,
+                state,
+                virtual_keycode: Some(key),
+                modifiers: ModifiersState::empty(),
+            },
+            is_synthetic: false,
+        }
+    }
+
+    #[test]
+    fn extend_replaces_listed_bindings() {
+        let mut map = ActionMap::default();
+        let config = ActionMap::new()
+            .with_binding("quit", Binding::Key(VirtualKeyCode::Q))
+            .with_binding("jump", Binding::Key(VirtualKeyCode::Space))
+            .with_axis_binding("zoom", AxisBinding::MouseY);
+        map.extend(&config);
+
+        assert_eq!(map.actions["quit"], [Binding::Key(VirtualKeyCode::Q)]);
+        assert_eq!(map.actions["jump"], [Binding::Key(VirtualKeyCode::Space)]);
+        assert_eq!(map.axes["zoom"], [AxisBinding::MouseY]);
+        assert_eq!(
+            map.actions["screenshot"],
+            ActionMap::default().actions["screenshot"]
+        );
+    }
+
+    #[test]
+    fn modifiers_gate_bindings() {
+        let map = ActionMap::new().with_binding(
+            "save",
+            Binding::With(
+                vec![Modifier::Ctrl],
+                Box::new(Binding::Key(VirtualKeyCode::S)),
+            ),
+        );
+        let mut input = Input::new();
+
+        step(
+            &mut input,
+            vec![key(VirtualKeyCode::S, ElementState::Pressed)],
+        );
+        assert!(!map.state(&input).pressed("save"));
+        assert!(!map.state(&input).held("save"));
+
+        step(
+            &mut input,
+            vec![
+                key(VirtualKeyCode::S, ElementState::Released),
+                WindowEvent::ModifiersChanged(ModifiersState::CTRL),
+                key(VirtualKeyCode::S, ElementState::Pressed),
+            ],
+        );
+        assert!(map.state(&input).pressed("save"));
+        assert!(map.state(&input).held("save"));
+
+        // Releasing the key counts even after letting go of the modifier
+        step(
+            &mut input,
+            vec![
+                WindowEvent::ModifiersChanged(ModifiersState::empty()),
+                key(VirtualKeyCode::S, ElementState::Released),
+            ],
+        );
+        assert!(map.state(&input).released("save"));
+    }
+
+    #[test]
+    fn axis_bindings_add_up() {
+        let keys = AxisBinding::Buttons(
+            Binding::Key(VirtualKeyCode::A),
+            Binding::Key(VirtualKeyCode::D),
+        );
+        let map = ActionMap::new()
+            .with_axis_binding("move_x", keys.clone())
+            .with_axis_binding("move_x", AxisBinding::Scaled(2.0, Box::new(keys)));
+        let mut input = Input::new();
+        assert_eq!(map.state(&input).axis("move_x"), 0.0);
+        assert_eq!(map.state(&input).axis("unbound"), 0.0);
+
+        step(
+            &mut input,
+            vec![key(VirtualKeyCode::A, ElementState::Pressed)],
+        );
+        assert_eq!(map.state(&input).axis("move_x"), -3.0);
+    }
+}
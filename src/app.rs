@@ -0,0 +1,32 @@
+//
+// app.rs
+//
+
+use super::engine::Engine;
+use winit::event::Event;
+
+/// Application callbacks
+///
+/// Implement this and pass it to `Engine::run` to hook into the main loop.
+/// Every callback has an empty default implementation, so only the
+/// ones needed have to be provided. Call `Engine::quit` from any of them
+/// to leave the main loop
+pub trait App {
+    /// Called once before entering the main loop
+    fn init(&mut self, _engine: &mut Engine) {}
+
+    /// Called for every fixed simulation step, after the engine's own step
+    fn update(&mut self, _engine: &mut Engine, _dt: f32) {}
+
+    /// Called for every winit event, before the engine handles it
+    fn on_event(&mut self, _engine: &mut Engine, _event: &Event<()>) {}
+
+    /// Called after the surface and the renderer have been resized
+    fn on_resize(&mut self, _engine: &mut Engine, _size: (u32, u32)) {}
+
+    /// Called once when the main loop exits
+    fn on_exit(&mut self, _engine: &mut Engine) {}
+}
+
+/// No-op application, for when the engine's own behavior is enough
+impl App for () {}
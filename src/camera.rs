@@ -3,6 +3,7 @@
 //
 
 use glam::{Mat4, Quat, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
 use std::f32::consts::PI;
 
 /// Camera
@@ -20,6 +21,28 @@ pub struct Camera {
     pub max_velocity: f32,
     pub angular_acceleration: f32,
     pub max_angular_velocity: f32,
+    pub prev_position: Vec3,
+    pub prev_rotation: Quat,
+    pub projection: Projection,
+}
+
+/// Camera projection, the aspect ratio comes from the render target
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Projection {
+    pub mode: ProjectionMode,
+    pub near: f32,
+    /// Ignored by reversed Z perspectives, which have no far plane
+    pub far: f32,
+    /// Maps the near plane to depth 1, spreading depth precision evenly
+    pub reversed_z: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ProjectionMode {
+    /// Vertical field of view in radians
+    Perspective { fov: f32 },
+    /// Height of the view volume in world units
+    Orthographic { height: f32 },
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -42,11 +65,6 @@ impl Camera {
     }
 
     pub fn move_to(&mut self, directions: &[CameraMoveDirection], dt: f32) {
-        let rotation = self.rotation;
-        let v = Mat4::from_quat(rotation);
-        let forward = v.z_axis.truncate();
-        let strafe = v.x_axis.truncate();
-
         let (mut dx, mut dz) = (0.0, 0.0);
         for d in directions {
             match d {
@@ -57,9 +75,18 @@ impl Camera {
             }
         }
 
-        dx *= self.acceleration * dt;
-        dz *= self.acceleration * dt;
-        self.velocity += (dz * forward) + (dx * strafe);
+        self.move_by(Vec2::new(dx, dz), dt);
+    }
+
+    /// Accelerates along the strafe (x) and forward (y) directions,
+    /// a unit `amount` giving full acceleration as with `move_to`
+    pub fn move_by(&mut self, amount: Vec2, dt: f32) {
+        let v = Mat4::from_quat(self.rotation);
+        let forward = v.z_axis.truncate();
+        let strafe = v.x_axis.truncate();
+
+        let d = amount * self.acceleration * dt;
+        self.velocity += (d.y * forward) + (d.x * strafe);
         self.velocity = self.velocity.clamp_length(0.0, self.max_velocity);
     }
 
@@ -75,9 +102,43 @@ impl Camera {
 
     pub fn set_position(&mut self, position: Vec3) {
         self.position = position;
+        self.prev_position = position;
+    }
+
+    /// Moves the camera to a new placement without interpolating from the previous one
+    pub fn set_pose(&mut self, position: Vec3, rotation: Quat) {
+        self.place(position, rotation);
+        self.prev_position = position;
+        self.prev_rotation = rotation;
+    }
+
+    /// Moves the camera to a new placement, keeping the previous one for interpolation
+    pub fn place(&mut self, position: Vec3, rotation: Quat) {
+        self.prev_position = self.position;
+        self.prev_rotation = self.rotation;
+        self.position = position;
+        self.rotation = rotation;
+    }
+
+    /// Turns the camera to face `target`
+    pub fn look_at(&mut self, target: Vec3) {
+        let dir = (target - self.position).normalize_or_zero();
+        if dir == Vec3::ZERO {
+            return;
+        }
+        self.angles = Vec3::new(-dir.y.asin(), dir.x.atan2(dir.z), 0.0);
+        self.angular_velocity = Vec3::ZERO;
+        let qp = Quat::from_rotation_x(self.angles.x);
+        let qy = Quat::from_rotation_y(self.angles.y);
+        self.rotation = (qy * qp).normalize();
+        self.prev_rotation = self.rotation;
     }
 
     pub fn update(&mut self, dt: f32) {
+        // Keep the previous state for interpolation
+        self.prev_position = self.position;
+        self.prev_rotation = self.rotation;
+
         // Movement
         self.position += self.velocity * dt;
         self.velocity *= (1e-4 as f32).powf(dt);
@@ -95,4 +156,78 @@ impl Camera {
         let r = Mat4::from_quat(self.rotation.conjugate());
         r * t
     }
+
+    /// View matrix blended between the previous and the current update by `alpha`
+    pub fn interpolated_matrix(&self, alpha: f32) -> Mat4 {
+        let position = self.prev_position.lerp(self.position, alpha);
+        let rotation = self.prev_rotation.slerp(self.rotation, alpha);
+        let t = Mat4::from_translation(-position);
+        let r = Mat4::from_quat(rotation.conjugate());
+        r * t
+    }
+}
+
+impl Projection {
+    pub fn perspective(fov: f32, near: f32, far: f32) -> Self {
+        Projection {
+            mode: ProjectionMode::Perspective { fov },
+            near,
+            far,
+            reversed_z: false,
+        }
+    }
+
+    pub fn orthographic(height: f32, near: f32, far: f32) -> Self {
+        Projection {
+            mode: ProjectionMode::Orthographic { height },
+            near,
+            far,
+            reversed_z: false,
+        }
+    }
+
+    pub fn with_reversed_z(self, reversed_z: bool) -> Self {
+        Projection { reversed_z, ..self }
+    }
+
+    /// Projection matrix for a render target of the given `aspect` ratio
+    pub fn matrix(&self, aspect: f32) -> Mat4 {
+        // Swapping the planes reverses the depth range
+        let (near, far) = match self.reversed_z {
+            true => (self.far, self.near),
+            false => (self.near, self.far),
+        };
+        match self.mode {
+            ProjectionMode::Perspective { fov } => match self.reversed_z {
+                true => Mat4::perspective_infinite_reverse_lh(fov, aspect, self.near),
+                false => Mat4::perspective_lh(fov, aspect, near, far),
+            },
+            ProjectionMode::Orthographic { height } => {
+                let (w, h) = (height * aspect / 2.0, height / 2.0);
+                Mat4::orthographic_lh(-w, w, -h, h, near, far)
+            }
+        }
+    }
+
+    /// Depth test passing fragments closer to the camera
+    pub fn depth_compare(&self) -> wgpu::CompareFunction {
+        match self.reversed_z {
+            true => wgpu::CompareFunction::Greater,
+            false => wgpu::CompareFunction::Less,
+        }
+    }
+
+    /// Depth of the far plane, cleared to before rendering
+    pub fn far_depth(&self) -> f32 {
+        match self.reversed_z {
+            true => 0.0,
+            false => 1.0,
+        }
+    }
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Projection::perspective(45f32.to_radians(), 0.1, 100.0)
+    }
 }
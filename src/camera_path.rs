@@ -0,0 +1,304 @@
+//
+// camera_path.rs
+//
+
+use super::camera::Camera;
+use glam::{Quat, Vec3};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt, fs, io, path::Path};
+
+/// Camera pose at a point in time
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// Seconds from the start of the path
+    pub time: f32,
+    pub position: Vec3,
+    pub rotation: Quat,
+}
+
+/// Camera move through keyframes, stored in RON files
+///
+/// Positions follow a spline through the keyframes and rotations are slerped,
+/// so the timing of the keyframes sets the speed along the path
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraPath {
+    /// Ordered by time
+    pub keyframes: Vec<Keyframe>,
+    pub interpolation: Interpolation,
+}
+
+/// How positions are interpolated between keyframes
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interpolation {
+    Linear,
+    /// Catmull-Rom spline parameterized by the keyframe times
+    CatmullRom,
+}
+
+/// Errors reported while loading or saving a CameraPath
+#[derive(Debug)]
+pub enum CameraPathError {
+    Io(io::Error),
+    Parse(ron::Error),
+    Serialize(ron::Error),
+    /// A keyframe comes before the one preceding it
+    Unordered(usize),
+}
+
+impl CameraPath {
+    /// Loads a path from a RON file
+    pub fn load(path: &Path) -> Result<Self, CameraPathError> {
+        let src = fs::read_to_string(path)?;
+        let camera_path: CameraPath = ron::from_str(&src).map_err(CameraPathError::Parse)?;
+        let keyframes = &camera_path.keyframes;
+        if let Some(i) = (1..keyframes.len()).find(|&i| keyframes[i].time < keyframes[i - 1].time) {
+            return Err(CameraPathError::Unordered(i));
+        }
+        Ok(camera_path)
+    }
+
+    /// Saves the path to a RON file
+    pub fn save(&self, path: &Path) -> Result<(), CameraPathError> {
+        let config = ron::ser::PrettyConfig::default();
+        let src = ron::ser::to_string_pretty(self, config).map_err(CameraPathError::Serialize)?;
+        fs::write(path, src)?;
+        Ok(())
+    }
+
+    /// Appends the current pose of `camera` as a keyframe at `time`.
+    /// Fails and leaves the path untouched if `time` precedes the last keyframe
+    pub fn record(&mut self, time: f32, camera: &Camera) -> Result<(), CameraPathError> {
+        if let Some(last) = self.keyframes.last() {
+            if time < last.time {
+                return Err(CameraPathError::Unordered(self.keyframes.len()));
+            }
+        }
+        self.keyframes.push(Keyframe {
+            time,
+            position: camera.position,
+            rotation: camera.rotation,
+        });
+        Ok(())
+    }
+
+    /// Time of the first keyframe
+    pub fn start(&self) -> f32 {
+        self.keyframes.first().map_or(0.0, |k| k.time)
+    }
+
+    /// Time from the first to the last keyframe
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |k| k.time) - self.start()
+    }
+
+    /// Pose at `time`, clamped to the keyframes. Returns None for empty paths
+    pub fn sample(&self, time: f32) -> Option<(Vec3, Quat)> {
+        let keys = &self.keyframes;
+        match keys.len() {
+            0 => return None,
+            1 => return Some((keys[0].position, keys[0].rotation)),
+            _ => (),
+        }
+
+        // Find the segment containing time
+        let i = keys
+            .partition_point(|k| k.time <= time)
+            .clamp(1, keys.len() - 1);
+        let (k1, k2) = (&keys[i - 1], &keys[i]);
+        let time = time.clamp(k1.time, k2.time);
+        let u = fraction(k1.time, k2.time, time);
+
+        // Take the shorter arc, slerp does not flip opposite hemispheres
+        let r2 = match k1.rotation.dot(k2.rotation) < 0.0 {
+            true => -k2.rotation,
+            false => k2.rotation,
+        };
+        let rotation = k1.rotation.slerp(r2, u);
+
+        let position = match self.interpolation {
+            Interpolation::Linear => k1.position.lerp(k2.position, u),
+            Interpolation::CatmullRom => {
+                // Extrapolate the missing neighbors of the end segments
+                let mirror = |k: &Keyframe, o: &Keyframe| Keyframe {
+                    time: 2.0 * k.time - o.time,
+                    position: 2.0 * k.position - o.position,
+                    rotation: k.rotation,
+                };
+                let k0 = match i {
+                    1 => mirror(k1, k2),
+                    _ => keys[i - 2],
+                };
+                let k3 = keys.get(i + 1).copied().unwrap_or_else(|| mirror(k2, k1));
+                catmull_rom([&k0, k1, k2, &k3], time)
+            }
+        };
+        Some((position, rotation))
+    }
+}
+
+impl Default for CameraPath {
+    fn default() -> Self {
+        CameraPath {
+            keyframes: vec![],
+            interpolation: Interpolation::CatmullRom,
+        }
+    }
+}
+
+/// Position along the non-uniform Catmull-Rom spline through `k`, between `k[1]` and `k[2]`,
+/// evaluated with the Barry-Goldman pyramid
+fn catmull_rom(k: [&Keyframe; 4], time: f32) -> Vec3 {
+    let mix = |a: &Keyframe, b: &Keyframe, pa: Vec3, pb: Vec3| {
+        pa.lerp(pb, fraction(a.time, b.time, time))
+    };
+    let a1 = mix(k[0], k[1], k[0].position, k[1].position);
+    let a2 = mix(k[1], k[2], k[1].position, k[2].position);
+    let a3 = mix(k[2], k[3], k[2].position, k[3].position);
+    let b1 = mix(k[0], k[2], a1, a2);
+    let b2 = mix(k[1], k[3], a2, a3);
+    mix(k[1], k[2], b1, b2)
+}
+
+/// Fraction of the way from `a` to `b` at `t`, zero for coincident times
+fn fraction(a: f32, b: f32, t: f32) -> f32 {
+    match b > a {
+        true => (t - a) / (b - a),
+        false => 0.0,
+    }
+}
+
+impl fmt::Display for CameraPathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CameraPathError::Io(e) => write!(f, "Failed to access camera path file: {}", e),
+            CameraPathError::Parse(e) => write!(f, "Failed to parse camera path: {}", e),
+            CameraPathError::Serialize(e) => write!(f, "Failed to serialize camera path: {}", e),
+            CameraPathError::Unordered(i) => {
+                write!(f, "Camera path keyframe {} precedes the previous one", i)
+            }
+        }
+    }
+}
+
+impl Error for CameraPathError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CameraPathError::Io(e) => Some(e),
+            CameraPathError::Parse(e) => Some(e),
+            CameraPathError::Serialize(e) => Some(e),
+            CameraPathError::Unordered(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for CameraPathError {
+    fn from(e: io::Error) -> Self {
+        CameraPathError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::assert_close;
+
+    fn path(interpolation: Interpolation, keys: &[(f32, Vec3)]) -> CameraPath {
+        CameraPath {
+            keyframes: keys
+                .iter()
+                .map(|&(time, position)| Keyframe {
+                    time,
+                    position,
+                    rotation: Quat::IDENTITY,
+                })
+                .collect(),
+            interpolation,
+        }
+    }
+
+    fn position(path: &CameraPath, time: f32) -> Vec3 {
+        path.sample(time).unwrap().0
+    }
+
+    #[test]
+    fn empty_and_single_keyframe_paths() {
+        assert_eq!(CameraPath::default().sample(0.0), None);
+        let single = path(Interpolation::CatmullRom, &[(1.0, Vec3::X)]);
+        assert_eq!(single.sample(5.0), Some((Vec3::X, Quat::IDENTITY)));
+    }
+
+    #[test]
+    fn samples_are_clamped_to_the_keyframes() {
+        let keys = [(1.0, Vec3::ZERO), (2.0, Vec3::X)];
+        let linear = path(Interpolation::Linear, &keys);
+        assert_close(position(&linear, 0.0), Vec3::ZERO);
+        assert_close(position(&linear, 1.25), Vec3::X * 0.25);
+        assert_close(position(&linear, 3.0), Vec3::X);
+    }
+
+    #[test]
+    fn spline_passes_through_keyframes() {
+        let keys = [
+            (0.0, Vec3::ZERO),
+            (1.0, Vec3::Y),
+            (3.0, Vec3::ONE),
+            (4.0, Vec3::Z),
+        ];
+        let spline = path(Interpolation::CatmullRom, &keys);
+        for &(time, p) in &keys {
+            assert_close(position(&spline, time), p);
+        }
+    }
+
+    #[test]
+    fn spline_end_segments_follow_straight_paths() {
+        // Mirrored neighbors keep evenly spaced keyframes on a line
+        let keys = [(0.0, Vec3::ZERO), (1.0, Vec3::X), (2.0, Vec3::X * 2.0)];
+        let spline = path(Interpolation::CatmullRom, &keys);
+        assert_close(position(&spline, 0.25), Vec3::X * 0.25);
+        assert_close(position(&spline, 1.75), Vec3::X * 1.75);
+    }
+
+    #[test]
+    fn coincident_keyframes_jump() {
+        let keys = [
+            (0.0, Vec3::ZERO),
+            (1.0, Vec3::X),
+            (1.0, Vec3::Y),
+            (2.0, Vec3::Z),
+        ];
+        for interpolation in [Interpolation::Linear, Interpolation::CatmullRom] {
+            let p = path(interpolation, &keys);
+            assert!(position(&p, 1.0).is_finite());
+            assert_close(position(&p, 0.0), Vec3::ZERO);
+            assert_close(position(&p, 2.0), Vec3::Z);
+        }
+    }
+
+    #[test]
+    fn rotations_take_the_shorter_arc() {
+        let mut p = path(
+            Interpolation::Linear,
+            &[(0.0, Vec3::ZERO), (1.0, Vec3::ZERO)],
+        );
+        let rotation = Quat::from_rotation_y(0.5);
+        p.keyframes[0].rotation = rotation;
+        p.keyframes[1].rotation = -rotation;
+        let (_, sampled) = p.sample(0.5).unwrap();
+        assert!(sampled.dot(rotation).abs() > 0.9999);
+    }
+
+    #[test]
+    fn record_keeps_keyframes_ordered() {
+        let mut p = CameraPath::default();
+        let camera = Camera::new();
+        p.record(1.0, &camera).unwrap();
+        p.record(1.0, &camera).unwrap();
+        assert!(matches!(
+            p.record(0.5, &camera),
+            Err(CameraPathError::Unordered(2))
+        ));
+        assert_eq!(p.keyframes.len(), 2);
+    }
+}
@@ -0,0 +1,105 @@
+//
+// clock.rs
+//
+
+use std::time::Instant;
+
+/// Frame clock
+///
+/// Measures real frame deltas and splits them into fixed simulation steps.
+/// Leftover time is kept in an accumulator, and its fraction of a step
+/// is exposed as `alpha` for interpolating between simulation states
+#[derive(Debug)]
+pub struct Clock {
+    /// Duration of a simulation step in seconds
+    pub step: f32,
+    /// Upper bound of a single frame delta in seconds, to avoid
+    /// simulating a huge number of steps after stalls
+    pub max_frame_time: f32,
+    /// Last measured (clamped) frame delta in seconds
+    pub frame_time: f32,
+    /// Interpolation factor between the previous and the current step
+    pub alpha: f32,
+    accumulator: f32,
+    last: Instant,
+}
+
+impl Clock {
+    pub fn new(step: f32) -> Self {
+        Clock {
+            step,
+            max_frame_time: 0.25,
+            frame_time: 0.0,
+            alpha: 0.0,
+            accumulator: 0.0,
+            last: Instant::now(),
+        }
+    }
+
+    /// Measures the time since the previous tick and
+    /// returns the number of fixed steps that have to be simulated
+    pub fn tick(&mut self) -> u32 {
+        let now = Instant::now();
+        let frame_time = (now - self.last).as_secs_f32();
+        self.last = now;
+        self.advance(frame_time)
+    }
+
+    /// Advances by a frame of `frame_time` seconds instead of measuring it and
+    /// returns the number of fixed steps that have to be simulated
+    pub fn advance(&mut self, frame_time: f32) -> u32 {
+        self.frame_time = frame_time.min(self.max_frame_time);
+        self.accumulator += self.frame_time;
+        let steps = (self.accumulator / self.step) as u32;
+        self.accumulator -= steps as f32 * self.step;
+        self.alpha = self.accumulator / self.step;
+        steps
+    }
+
+    /// Restarts the measurement, discarding any accumulated time
+    pub fn reset(&mut self) {
+        self.accumulator = 0.0;
+        self.alpha = 0.0;
+        self.last = Instant::now();
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::new(1.0 / 60.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_time_is_clamped() {
+        let mut clock = Clock::new(0.125);
+        assert_eq!(clock.advance(1.0), 2);
+        assert_eq!(clock.frame_time, 0.25);
+        assert_eq!(clock.alpha, 0.0);
+    }
+
+    #[test]
+    fn leftover_time_accumulates_into_alpha() {
+        let mut clock = Clock::new(0.125);
+        assert_eq!(clock.advance(0.0625), 0);
+        assert_eq!(clock.alpha, 0.5);
+        assert_eq!(clock.advance(0.125), 1);
+        assert_eq!(clock.alpha, 0.5);
+        assert_eq!(clock.advance(0.0625), 1);
+        assert_eq!(clock.alpha, 0.0);
+    }
+
+    #[test]
+    fn reset_discards_accumulated_time() {
+        let mut clock = Clock::new(0.125);
+        clock.advance(0.1);
+        clock.reset();
+        assert_eq!(clock.alpha, 0.0);
+        assert_eq!(clock.tick(), 0);
+        assert!(clock.frame_time <= clock.max_frame_time);
+    }
+}
@@ -0,0 +1,311 @@
+//
+// config.rs
+//
+
+use super::{
+    action::ActionMap,
+    engine::{EngineParams, FullscreenMode},
+};
+use serde::Deserialize;
+use std::{env, error::Error, fmt, fs, io, path::Path};
+
+/// Engine settings as read from a config file or the environment
+///
+/// Every field is optional, and only the ones that are set
+/// override the `EngineParams` they are applied to
+#[derive(Default, Debug, Deserialize)]
+#[serde(default)]
+pub struct EngineConfig {
+    pub size: Option<(u32, u32)>,
+    pub title: Option<String>,
+    pub resizable: Option<bool>,
+    /// One of `windowed`, `borderless` or `exclusive`
+    pub fullscreen: Option<String>,
+    /// Comma separated list of backends, e.g. `vulkan,gl`
+    pub backends: Option<String>,
+    /// Either `low` or `high`
+    pub power_preference: Option<String>,
+    /// One of `fifo`, `mailbox` or `immediate`
+    pub present_mode: Option<String>,
+    /// Case insensitive substring of the adapter name
+    pub adapter: Option<String>,
+    pub fallback_adapter: Option<bool>,
+    /// Comma separated device features requested on top of the params' ones,
+    /// named like the `wgpu::Features` flags, e.g. `texture_compression_bc,polygon_mode_line`
+    pub features: Option<String>,
+    /// Device limits preset, one of `default`, `downlevel` or `webgl2`
+    pub limits: Option<String>,
+    /// Replaces the default bindings of the actions and axes it lists,
+    /// e.g. `bindings: Some((actions: {"quit": [Key(Q)]}))`
+    pub bindings: Option<ActionMap>,
+}
+
+/// Errors reported while loading or applying an EngineConfig
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(ron::Error),
+    InvalidValue { key: &'static str, value: String },
+}
+
+impl EngineConfig {
+    /// Environment variable prefix, e.g. `NATSUKASHII_PRESENT_MODE=mailbox`
+    pub const ENV_PREFIX: &'static str = "NATSUKASHII_";
+
+    /// Loads the config from a RON file
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let src = fs::read_to_string(path)?;
+        Ok(ron::from_str(&src)?)
+    }
+
+    /// Loads the config from `NATSUKASHII_*` environment variables
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let var = |key: &str| env::var(format!("{}{}", Self::ENV_PREFIX, key)).ok();
+        let size = var("SIZE")
+            .map(|v| parse_size(&v).ok_or_else(|| invalid("size", &v)))
+            .transpose()?;
+        let resizable = var("RESIZABLE")
+            .map(|v| parse_bool("resizable", v))
+            .transpose()?;
+        let fallback_adapter = var("FALLBACK_ADAPTER")
+            .map(|v| parse_bool("fallback_adapter", v))
+            .transpose()?;
+
+        Ok(EngineConfig {
+            size,
+            title: var("TITLE"),
+            resizable,
+            fullscreen: var("FULLSCREEN"),
+            backends: var("BACKENDS"),
+            power_preference: var("POWER_PREFERENCE"),
+            present_mode: var("PRESENT_MODE"),
+            adapter: var("ADAPTER"),
+            fallback_adapter,
+            features: var("FEATURES"),
+            limits: var("LIMITS"),
+            bindings: None,
+        })
+    }
+
+    /// Overrides the given params with every setting present in this config.
+    /// Invalid values are reported under the config file key, e.g. `present_mode`
+    pub fn apply(&self, mut params: EngineParams) -> Result<EngineParams, ConfigError> {
+        if let Some(size) = self.size {
+            params.window.size = size;
+        }
+        if let Some(title) = &self.title {
+            params.window.title = title.clone();
+        }
+        if let Some(resizable) = self.resizable {
+            params.window.resizable = resizable;
+        }
+        if let Some(v) = &self.fullscreen {
+            params.window.fullscreen = match v.to_lowercase().as_str() {
+                "windowed" => FullscreenMode::Windowed,
+                "borderless" => FullscreenMode::Borderless,
+                "exclusive" => FullscreenMode::Exclusive,
+                _ => return Err(invalid("fullscreen", v)),
+            };
+        }
+        if let Some(v) = &self.backends {
+            let backends = wgpu::util::parse_backends_from_comma_list(&v.to_lowercase());
+            if backends.is_empty() {
+                return Err(invalid("backends", v));
+            }
+            params.backends = backends;
+        }
+        if let Some(v) = &self.power_preference {
+            params.power_preference = match v.to_lowercase().as_str() {
+                "low" => wgpu::PowerPreference::LowPower,
+                "high" => wgpu::PowerPreference::HighPerformance,
+                _ => return Err(invalid("power_preference", v)),
+            };
+        }
+        if let Some(v) = &self.present_mode {
+            params.present_mode = match v.to_lowercase().as_str() {
+                "fifo" => wgpu::PresentMode::Fifo,
+                "mailbox" => wgpu::PresentMode::Mailbox,
+                "immediate" => wgpu::PresentMode::Immediate,
+                _ => return Err(invalid("present_mode", v)),
+            };
+        }
+        if let Some(adapter) = &self.adapter {
+            params.adapter_name = Some(adapter.clone());
+        }
+        if let Some(fallback) = self.fallback_adapter {
+            params.force_fallback_adapter = fallback;
+        }
+        if let Some(v) = &self.features {
+            params.features |= parse_features(v).ok_or_else(|| invalid("features", v))?;
+        }
+        if let Some(v) = &self.limits {
+            params.limits = Some(match v.to_lowercase().as_str() {
+                "default" => wgpu::Limits::default(),
+                "downlevel" => wgpu::Limits::downlevel_defaults(),
+                "webgl2" => wgpu::Limits::downlevel_webgl2_defaults(),
+                _ => return Err(invalid("limits", v)),
+            });
+        }
+        if let Some(bindings) = &self.bindings {
+            params.actions.extend(bindings);
+        }
+        Ok(params)
+    }
+}
+
+fn invalid(key: &'static str, value: &str) -> ConfigError {
+    ConfigError::InvalidValue {
+        key,
+        value: value.to_owned(),
+    }
+}
+
+/// Parses a `WIDTHxHEIGHT` size, e.g. `1280x720`
+pub fn parse_size(v: &str) -> Option<(u32, u32)> {
+    let (w, h) = v.split_once('x')?;
+    Some((w.trim().parse().ok()?, h.trim().parse().ok()?))
+}
+
+/// Parses comma separated `wgpu::Features` flag names, case insensitively
+fn parse_features(v: &str) -> Option<wgpu::Features> {
+    let mut names = v.split(',').map(str::trim).filter(|name| !name.is_empty());
+    names.try_fold(wgpu::Features::empty(), |features, name| {
+        // Single flags print as their name
+        let feature = (0..64)
+            .filter_map(|bit| wgpu::Features::from_bits(1 << bit))
+            .find(|f| format!("{:?}", f).eq_ignore_ascii_case(name))?;
+        Some(features | feature)
+    })
+}
+
+fn parse_bool(key: &'static str, v: String) -> Result<bool, ConfigError> {
+    match v.to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(ConfigError::InvalidValue { key, value: v }),
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "Failed to read config: {}", e),
+            ConfigError::Parse(e) => write!(f, "Failed to parse config: {}", e),
+            ConfigError::InvalidValue { key, value } => {
+                write!(f, "Invalid value {:?} for config key {}", value, key)
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io(e) => Some(e),
+            ConfigError::Parse(e) => Some(e),
+            ConfigError::InvalidValue { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<ron::Error> for ConfigError {
+    fn from(e: ron::Error) -> Self {
+        ConfigError::Parse(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::Binding;
+    use winit::event::VirtualKeyCode;
+
+    #[test]
+    fn sizes_parse() {
+        assert_eq!(parse_size("640x480"), Some((640, 480)));
+        assert_eq!(parse_size(" 800 x 600 "), Some((800, 600)));
+        assert_eq!(parse_size("640"), None);
+        assert_eq!(parse_size("640x"), None);
+        assert_eq!(parse_size("-1x480"), None);
+    }
+
+    #[test]
+    fn bools_parse() {
+        assert!(parse_bool("resizable", "Yes".to_owned()).unwrap());
+        assert!(parse_bool("resizable", "1".to_owned()).unwrap());
+        assert!(!parse_bool("resizable", "off".to_owned()).unwrap());
+        match parse_bool("resizable", "maybe".to_owned()) {
+            Err(ConfigError::InvalidValue { key, value }) => {
+                assert_eq!((key, value.as_str()), ("resizable", "maybe"));
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn features_parse() {
+        assert_eq!(parse_features(""), Some(wgpu::Features::empty()));
+        assert_eq!(
+            parse_features("texture_compression_bc, POLYGON_MODE_LINE"),
+            Some(wgpu::Features::TEXTURE_COMPRESSION_BC | wgpu::Features::POLYGON_MODE_LINE)
+        );
+        assert_eq!(parse_features("texture_compression_bc,teleport"), None);
+    }
+
+    #[test]
+    fn apply_overrides_only_set_values() {
+        let config: EngineConfig = ron::from_str(
+            r#"(
+                size: Some((320, 240)),
+                fullscreen: Some("Borderless"),
+                power_preference: Some("low"),
+                features: Some("depth_clamping"),
+                limits: Some("Downlevel"),
+                bindings: Some((actions: {"quit": [Key(Q)]})),
+            )"#,
+        )
+        .unwrap();
+        let params = config.apply(EngineParams::default()).unwrap();
+        assert_eq!(params.window.size, (320, 240));
+        assert_eq!(params.window.fullscreen, FullscreenMode::Borderless);
+        assert_eq!(params.power_preference, wgpu::PowerPreference::LowPower);
+        assert_eq!(params.window.title, EngineParams::default().window.title);
+        assert_eq!(params.present_mode, wgpu::PresentMode::Fifo);
+        assert_eq!(params.features, wgpu::Features::DEPTH_CLAMPING);
+        assert_eq!(
+            params.limits.unwrap().max_texture_dimension_2d,
+            wgpu::Limits::downlevel_defaults().max_texture_dimension_2d
+        );
+        assert_eq!(
+            params.actions.actions["quit"],
+            vec![Binding::Key(VirtualKeyCode::Q)]
+        );
+        assert!(params.actions.actions.contains_key("screenshot"));
+    }
+
+    #[test]
+    fn apply_rejects_unknown_values() {
+        let config = EngineConfig {
+            present_mode: Some("sometimes".to_owned()),
+            ..Default::default()
+        };
+        match config.apply(EngineParams::default()) {
+            Err(ConfigError::InvalidValue { key, .. }) => assert_eq!(key, "present_mode"),
+            other => panic!("Unexpected {:?}", other.map(|_| ())),
+        }
+        let config = EngineConfig {
+            limits: Some("unlimited".to_owned()),
+            ..Default::default()
+        };
+        match config.apply(EngineParams::default()) {
+            Err(ConfigError::InvalidValue { key, .. }) => assert_eq!(key, "limits"),
+            other => panic!("Unexpected {:?}", other.map(|_| ())),
+        }
+    }
+}
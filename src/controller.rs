@@ -0,0 +1,314 @@
+//
+// controller.rs
+//
+
+use super::{
+    action::ActionState,
+    camera::{Camera, CameraMoveDirection},
+    camera_path::CameraPath,
+};
+use glam::{Quat, Vec2, Vec3};
+use std::f32::consts::FRAC_PI_2;
+
+/// Drives a Camera from user input
+///
+/// The engine calls `input` once per frame with the actions gathered during it,
+/// and `step` for every fixed simulation step. Relative input like mouse motion
+/// belongs in `input`, while rates like stick deflection are integrated in `step`
+/// so they do not depend on the frame rate. See `Engine::set_controller`
+/// and `ActionMap` for the actions and axes each controller reads
+pub trait CameraController {
+    /// Takes over `camera` from its current placement
+    fn attach(&mut self, camera: &mut Camera);
+
+    /// Reacts to the input of the last frame, `grabbed` telling if the cursor is grabbed
+    fn input(&mut self, camera: &mut Camera, actions: &ActionState, grabbed: bool, dt: f32);
+
+    /// Advances `camera` by a single fixed step of `dt` seconds
+    fn step(&mut self, camera: &mut Camera, actions: &ActionState, dt: f32);
+
+    /// Whether clicking into the window grabs the cursor
+    fn grabs_cursor(&self) -> bool {
+        false
+    }
+}
+
+/// First person movement with the `move_*` actions and the analog `move_x` and `move_z` axes,
+/// looking around with the `look_*` axes while the cursor is grabbed
+/// and with the `turn_*` axes at any time
+#[derive(Default, Debug)]
+pub struct FlyController;
+
+/// Free rotation around a target point while `orbit_rotate` is held or with the `turn_*` axes,
+/// zoom on the `zoom` axis and panning while `orbit_pan` is held
+#[derive(Debug)]
+pub struct OrbitController {
+    pub target: Vec3,
+    pub distance: f32,
+    pub rotation: Quat,
+    pub speeds: OrbitSpeeds,
+}
+
+/// Orbit around a target point keeping the world up axis upright,
+/// with the same bindings as `OrbitController`
+#[derive(Debug)]
+pub struct TurntableController {
+    pub target: Vec3,
+    pub distance: f32,
+    /// Rotation around the world up axis
+    pub yaw: f32,
+    /// Rotation above the horizon, kept short of the poles
+    pub pitch: f32,
+    pub speeds: OrbitSpeeds,
+}
+
+/// Plays back a recorded CameraPath, ignoring user input
+#[derive(Debug)]
+pub struct PathController {
+    pub path: CameraPath,
+    /// Playback position in seconds along the path
+    pub time: f32,
+    /// Playback rate, 1 keeps the recorded timing
+    pub speed: f32,
+    /// Restarts from the first keyframe after the last one
+    pub looping: bool,
+}
+
+/// Input sensitivity of the orbiting controllers
+#[derive(Copy, Clone, Debug)]
+pub struct OrbitSpeeds {
+    /// Radians per pixel of mouse motion
+    pub rotate: f32,
+    /// Fraction of the distance per pixel of mouse motion
+    pub pan: f32,
+    /// Fraction of the distance per unit of the `zoom` axis, a scroll wheel line by default
+    pub zoom: f32,
+    pub min_distance: f32,
+}
+
+impl CameraController for FlyController {
+    fn attach(&mut self, camera: &mut Camera) {
+        // Recover the look angles of the current rotation
+        camera.velocity = Vec3::ZERO;
+        camera.look_at(camera.position + camera.rotation * Vec3::Z);
+    }
+
+    fn input(&mut self, camera: &mut Camera, actions: &ActionState, grabbed: bool, dt: f32) {
+        if grabbed {
+            camera.look(look(actions), dt);
+        }
+    }
+
+    fn step(&mut self, camera: &mut Camera, actions: &ActionState, dt: f32) {
+        let camactions = [
+            ("move_forward", CameraMoveDirection::Forward),
+            ("move_left", CameraMoveDirection::Left),
+            ("move_backward", CameraMoveDirection::Backward),
+            ("move_right", CameraMoveDirection::Right),
+        ];
+        let dirs = camactions
+            .iter()
+            .filter(|k| actions.held(k.0))
+            .map(|k| k.1)
+            .collect::<Vec<_>>();
+        camera.move_to(&dirs, dt);
+
+        // Analog movement, e.g. from a gamepad stick
+        let amount = Vec2::new(actions.axis("move_x"), actions.axis("move_z"));
+        camera.move_by(amount.clamp_length_max(1.0), dt);
+        // Camera::look already scales by the step
+        camera.look(turn(actions), dt);
+        camera.update(dt);
+    }
+
+    fn grabs_cursor(&self) -> bool {
+        true
+    }
+}
+
+impl OrbitController {
+    pub fn new(target: Vec3) -> Self {
+        OrbitController {
+            target,
+            distance: 1.0,
+            rotation: Quat::IDENTITY,
+            speeds: OrbitSpeeds::default(),
+        }
+    }
+
+    /// Rotates around the camera axes by `d` pixels of mouse motion,
+    /// allowing to roll over the poles
+    fn rotate(&mut self, d: Vec2) {
+        let d = d * self.speeds.rotate;
+        if d != Vec2::ZERO {
+            let delta = Quat::from_rotation_y(d.x) * Quat::from_rotation_x(d.y);
+            self.rotation = (self.rotation * delta).normalize();
+        }
+    }
+}
+
+impl CameraController for OrbitController {
+    fn attach(&mut self, camera: &mut Camera) {
+        let (distance, rotation) = aim(camera, self.target, self.speeds.min_distance);
+        self.distance = distance;
+        self.rotation = rotation;
+    }
+
+    fn input(&mut self, _camera: &mut Camera, actions: &ActionState, _grabbed: bool, _dt: f32) {
+        self.rotate(orbit(actions));
+        self.target += pan(self.rotation, self.distance, &self.speeds, actions);
+        self.distance = zoom(self.distance, &self.speeds, actions);
+    }
+
+    fn step(&mut self, camera: &mut Camera, actions: &ActionState, dt: f32) {
+        self.rotate(turn(actions) * dt);
+        let position = self.target - self.rotation * Vec3::Z * self.distance;
+        camera.place(position, self.rotation);
+    }
+}
+
+impl TurntableController {
+    pub fn new(target: Vec3) -> Self {
+        TurntableController {
+            target,
+            distance: 1.0,
+            yaw: 0.0,
+            pitch: 0.0,
+            speeds: OrbitSpeeds::default(),
+        }
+    }
+
+    fn rotation(&self) -> Quat {
+        Quat::from_rotation_y(self.yaw) * Quat::from_rotation_x(self.pitch)
+    }
+
+    /// Turns by `d` pixels of mouse motion, stopping short of the poles
+    fn rotate(&mut self, d: Vec2) {
+        let d = d * self.speeds.rotate;
+        if d != Vec2::ZERO {
+            let limit = FRAC_PI_2 - 0.01;
+            self.yaw += d.x;
+            self.pitch = (self.pitch + d.y).clamp(-limit, limit);
+        }
+    }
+}
+
+impl CameraController for TurntableController {
+    fn attach(&mut self, camera: &mut Camera) {
+        let (distance, _) = aim(camera, self.target, self.speeds.min_distance);
+        self.distance = distance;
+        self.yaw = camera.angles.y;
+        self.pitch = camera.angles.x;
+    }
+
+    fn input(&mut self, _camera: &mut Camera, actions: &ActionState, _grabbed: bool, _dt: f32) {
+        self.rotate(orbit(actions));
+        self.target += pan(self.rotation(), self.distance, &self.speeds, actions);
+        self.distance = zoom(self.distance, &self.speeds, actions);
+    }
+
+    fn step(&mut self, camera: &mut Camera, actions: &ActionState, dt: f32) {
+        self.rotate(turn(actions) * dt);
+        let rotation = self.rotation();
+        let position = self.target - rotation * Vec3::Z * self.distance;
+        camera.place(position, rotation);
+    }
+}
+
+impl PathController {
+    pub fn new(path: CameraPath) -> Self {
+        PathController {
+            time: path.start(),
+            path,
+            speed: 1.0,
+            looping: false,
+        }
+    }
+
+    /// Whether playback has passed the last keyframe of a non looping path
+    pub fn finished(&self) -> bool {
+        !self.looping && self.time >= self.path.start() + self.path.duration()
+    }
+}
+
+impl CameraController for PathController {
+    fn attach(&mut self, camera: &mut Camera) {
+        camera.velocity = Vec3::ZERO;
+        self.time = self.path.start();
+        if let Some((position, rotation)) = self.path.sample(self.time) {
+            camera.set_pose(position, rotation);
+        }
+    }
+
+    fn input(&mut self, _camera: &mut Camera, _actions: &ActionState, _grabbed: bool, _dt: f32) {}
+
+    fn step(&mut self, camera: &mut Camera, _actions: &ActionState, dt: f32) {
+        // Advance playback, wrapping around when looping
+        let (start, duration) = (self.path.start(), self.path.duration());
+        let time = self.time + dt * self.speed;
+        self.time = match self.looping && duration > 0.0 {
+            true => start + (time - start).rem_euclid(duration),
+            false => time,
+        };
+        if let Some((position, rotation)) = self.path.sample(self.time) {
+            // Jump back to the start instead of interpolating across the whole path
+            match self.time < time {
+                true => camera.set_pose(position, rotation),
+                false => camera.place(position, rotation),
+            }
+        }
+    }
+}
+
+impl Default for OrbitSpeeds {
+    fn default() -> Self {
+        OrbitSpeeds {
+            rotate: 0.005,
+            pan: 0.001,
+            zoom: 0.1,
+            min_distance: 0.01,
+        }
+    }
+}
+
+/// Turns `camera` towards `target`, returning its distance and new rotation
+fn aim(camera: &mut Camera, target: Vec3, min_distance: f32) -> (f32, Quat) {
+    camera.velocity = Vec3::ZERO;
+    camera.look_at(target);
+    let distance = (target - camera.position).length().max(min_distance);
+    (distance, camera.rotation)
+}
+
+/// Look axes of the last frame, in pixels of mouse motion
+fn look(actions: &ActionState) -> Vec2 {
+    Vec2::new(actions.axis("look_x"), actions.axis("look_y"))
+}
+
+/// Turn axes as rates, in pixels of mouse motion per second
+fn turn(actions: &ActionState) -> Vec2 {
+    Vec2::new(actions.axis("turn_x"), actions.axis("turn_y"))
+}
+
+/// Mouse rotation of the orbiting controllers while `orbit_rotate` is held, in pixels
+fn orbit(actions: &ActionState) -> Vec2 {
+    match actions.held("orbit_rotate") {
+        true => look(actions),
+        false => Vec2::ZERO,
+    }
+}
+
+/// Target offset along the view plane while `orbit_pan` is held
+fn pan(rotation: Quat, distance: f32, speeds: &OrbitSpeeds, actions: &ActionState) -> Vec3 {
+    if !actions.held("orbit_pan") {
+        return Vec3::ZERO;
+    }
+    // Drag the scene along with the cursor
+    let d = look(actions) * speeds.pan * distance;
+    rotation * Vec3::new(-d.x, d.y, 0.0)
+}
+
+/// Distance scaled by the `zoom` axis, positive values zoom in
+fn zoom(distance: f32, speeds: &OrbitSpeeds, actions: &ActionState) -> f32 {
+    let lines = actions.axis("zoom");
+    (distance * (-lines * speeds.zoom).exp()).max(speeds.min_distance)
+}
@@ -3,32 +3,52 @@
 //
 
 use super::{
-    camera::{Camera, CameraMoveDirection},
+    action::{ActionMap, ActionState},
+    app::App,
+    camera::Camera,
+    camera_path::CameraPath,
+    clock::Clock,
+    config::{ConfigError, EngineConfig},
+    controller::{CameraController, FlyController},
+    error::EngineError,
     input::Input,
+    model::{Material, Model},
+    offscreen::Offscreen,
     renderer::{Renderer, RendererScene},
-    scene::Scene,
+    scene::{ObjectId, Scene, SceneCamera, SceneError, SceneObject, Transform},
 };
 
-use glam::Vec3;
+use gilrs::Gilrs;
+use glam::{Mat4, Vec3};
+use image::RgbaImage;
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
 use winit::{
     dpi::LogicalSize,
-    event::{ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent},
+    event::{Event, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::Window,
-    window::WindowBuilder,
+    window::{Fullscreen, Window, WindowBuilder},
 };
 
 /// The Engine
 ///
 /// Manages initialization, lifetime and plumbing of
-/// the window, the event loop and the renderer
+/// the window, the event loop and the renderer.
+/// When created headless there is no window, event loop or surface,
+/// and frames are rendered to an offscreen texture instead
 pub struct Engine {
     pub event_loop: Option<EventLoop<()>>,
-    pub window: Window,
+    pub window: Option<Window>,
     pub input: Input,
+    pub actions: ActionMap,
+    /// Gamepad context, None when headless or unsupported by the platform
+    pub gilrs: Option<Gilrs>,
     pub instance: wgpu::Instance,
-    pub surface: wgpu::Surface,
+    pub surface: Option<wgpu::Surface>,
     pub surface_conf: wgpu::SurfaceConfiguration,
+    pub offscreen: Option<Offscreen>,
     pub adapter: wgpu::Adapter,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
@@ -36,77 +56,336 @@ pub struct Engine {
     pub renderer_scene: RendererScene,
     pub scene: Scene,
     pub camera: Camera,
+    pub controller: Box<dyn CameraController>,
+    pub clock: Clock,
     pub state: EngineState,
 }
 
 /// Initialization parameters for Engine
+///
+/// Built with the `with_*` methods on top of the defaults,
+/// or loaded from an `EngineConfig`
+#[derive(Clone, Debug)]
 pub struct EngineParams {
     pub window: WindowParams,
+    pub backends: wgpu::Backends,
+    pub power_preference: wgpu::PowerPreference,
+    pub present_mode: wgpu::PresentMode,
+    pub features: wgpu::Features,
+    /// Device limits, `Limits::default()` for windowed engines
+    /// and `Limits::downlevel_defaults()` for headless ones when unset
+    pub limits: Option<wgpu::Limits>,
+    /// Picks an adapter whose name contains this, case insensitively.
+    /// The power preference ranks the matching adapters
+    pub adapter_name: Option<String>,
+    /// Only considers software adapters
+    pub force_fallback_adapter: bool,
+    /// Bindings of the engine and camera controller actions
+    pub actions: ActionMap,
 }
 
 /// Initialization parameters for Window
+///
+/// Headless engines only use the size, for their offscreen target
+#[derive(Clone, Debug)]
 pub struct WindowParams {
     pub size: (u32, u32),
+    pub title: String,
+    pub resizable: bool,
+    pub fullscreen: FullscreenMode,
+}
+
+/// Fullscreen modes of the Window
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FullscreenMode {
+    Windowed,
+    Borderless,
+    /// Uses the largest video mode of the primary monitor
+    Exclusive,
 }
 
 /// Supplemental engine state
 pub struct EngineState {
     pub cursor_grabbed: bool,
+    pub quit_requested: bool,
+}
+
+impl Default for EngineParams {
+    fn default() -> Self {
+        EngineParams {
+            window: WindowParams::default(),
+            backends: wgpu::Backends::PRIMARY | wgpu::Backends::SECONDARY,
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            present_mode: wgpu::PresentMode::Fifo,
+            features: wgpu::Features::empty(),
+            limits: None,
+            adapter_name: None,
+            force_fallback_adapter: false,
+            actions: ActionMap::default(),
+        }
+    }
+}
+
+impl Default for WindowParams {
+    fn default() -> Self {
+        WindowParams {
+            size: (1280, 720),
+            title: "natsukashii".to_owned(),
+            resizable: false,
+            fullscreen: FullscreenMode::Windowed,
+        }
+    }
+}
+
+impl EngineParams {
+    pub fn with_size(mut self, size: (u32, u32)) -> Self {
+        self.window.size = size;
+        self
+    }
+
+    pub fn with_title(mut self, title: &str) -> Self {
+        self.window.title = title.to_owned();
+        self
+    }
+
+    pub fn with_resizable(mut self, resizable: bool) -> Self {
+        self.window.resizable = resizable;
+        self
+    }
+
+    pub fn with_fullscreen(mut self, fullscreen: FullscreenMode) -> Self {
+        self.window.fullscreen = fullscreen;
+        self
+    }
+
+    pub fn with_backends(mut self, backends: wgpu::Backends) -> Self {
+        self.backends = backends;
+        self
+    }
+
+    pub fn with_power_preference(mut self, power_preference: wgpu::PowerPreference) -> Self {
+        self.power_preference = power_preference;
+        self
+    }
+
+    pub fn with_present_mode(mut self, present_mode: wgpu::PresentMode) -> Self {
+        self.present_mode = present_mode;
+        self
+    }
+
+    pub fn with_features(mut self, features: wgpu::Features) -> Self {
+        self.features = features;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: wgpu::Limits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    pub fn with_adapter_name(mut self, name: &str) -> Self {
+        self.adapter_name = Some(name.to_owned());
+        self
+    }
+
+    pub fn with_fallback_adapter(mut self, force: bool) -> Self {
+        self.force_fallback_adapter = force;
+        self
+    }
+
+    pub fn with_actions(mut self, actions: ActionMap) -> Self {
+        self.actions = actions;
+        self
+    }
+
+    /// Overrides these params with the settings present in `config`
+    pub fn with_config(self, config: &EngineConfig) -> Result<Self, ConfigError> {
+        config.apply(self)
+    }
 }
 
 impl Engine {
-    pub async fn new(params: &EngineParams) -> Self {
+    pub async fn new(params: &EngineParams) -> Result<Self, EngineError> {
         // Create window and its event loop
         let event_loop = EventLoop::new();
+        let fullscreen = match params.window.fullscreen {
+            FullscreenMode::Windowed => None,
+            FullscreenMode::Borderless => Some(Fullscreen::Borderless(None)),
+            FullscreenMode::Exclusive => event_loop
+                .primary_monitor()
+                .and_then(|m| {
+                    m.video_modes()
+                        .max_by_key(|v| (v.size().width * v.size().height, v.refresh_rate()))
+                })
+                .map(Fullscreen::Exclusive)
+                .or(Some(Fullscreen::Borderless(None))),
+        };
         let window = WindowBuilder::new()
+            .with_title(&params.window.title)
             .with_inner_size(LogicalSize::<u32>::from(params.window.size))
-            .with_resizable(false)
-            .build(&event_loop)
-            .unwrap();
-
-        // Create input cache
-        let input = Input::new();
+            .with_resizable(params.window.resizable)
+            .with_fullscreen(fullscreen)
+            .build(&event_loop)?;
 
         // Create wgpu instance, surface and adapter
-        let instance = wgpu::Instance::new(wgpu::Backends::PRIMARY | wgpu::Backends::SECONDARY);
+        let instance = wgpu::Instance::new(params.backends);
         let surface = unsafe { instance.create_surface(&window) };
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                // Request an adapter which can render to our surface
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .expect("Failed to find an appropriate adapter");
+        let adapter = Self::request_adapter(&instance, params, Some(&surface)).await?;
 
         // Create the logical device and command queue
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    features: wgpu::Features::empty(),
-                    limits: wgpu::Limits::default(),
-                },
-                None,
-            )
-            .await
-            .expect("Failed to create device");
+        let limits = params.limits.clone().unwrap_or_default();
+        let (device, queue) = Self::request_device(&adapter, params, limits).await?;
 
         // Configure surface
         let size = window.inner_size();
-        let surface_format = surface.get_preferred_format(&adapter).unwrap();
+        let surface_format = surface
+            .get_preferred_format(&adapter)
+            .ok_or(EngineError::IncompatibleSurface)?;
         let surface_conf = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
+            present_mode: params.present_mode,
         };
         surface.configure(&device, &surface_conf);
 
+        // Store objects, with gamepad support
+        let mut engine = Self::from_parts(
+            Some(event_loop),
+            Some(window),
+            instance,
+            Some(surface),
+            surface_conf,
+            adapter,
+            device,
+            queue,
+            params.actions.clone(),
+        );
+        engine.gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(e) => {
+                log::warn!("Gamepads are unavailable: {}", e);
+                None
+            }
+        };
+        Ok(engine)
+    }
+
+    pub async fn new_headless(params: &EngineParams) -> Result<Self, EngineError> {
+        // Create wgpu instance and adapter, no surface to be compatible with
+        let instance = wgpu::Instance::new(params.backends);
+        let adapter = Self::request_adapter(&instance, params, None).await?;
+
+        // Create the logical device and command queue
+        let limits = params
+            .limits
+            .clone()
+            .unwrap_or_else(wgpu::Limits::downlevel_defaults);
+        let (device, queue) = Self::request_device(&adapter, params, limits).await?;
+
+        // Describe the offscreen target in place of a surface
+        let surface_conf = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: Offscreen::FORMAT,
+            width: params.window.size.0,
+            height: params.window.size.1,
+            present_mode: params.present_mode,
+        };
+
+        Ok(Self::from_parts(
+            None,
+            None,
+            instance,
+            None,
+            surface_conf,
+            adapter,
+            device,
+            queue,
+            params.actions.clone(),
+        ))
+    }
+
+    async fn request_adapter(
+        instance: &wgpu::Instance,
+        params: &EngineParams,
+        surface: Option<&wgpu::Surface>,
+    ) -> Result<wgpu::Adapter, EngineError> {
+        let adapter = match &params.adapter_name {
+            // Pick adapter by name, it must still be able to render to our surface
+            Some(name) => {
+                let name = name.to_lowercase();
+                instance
+                    .enumerate_adapters(params.backends)
+                    .filter(|a| {
+                        let info = a.get_info();
+                        let supported = match surface {
+                            Some(s) => a.is_surface_supported(s),
+                            None => true,
+                        };
+                        supported
+                            && info.name.to_lowercase().contains(&name)
+                            && (!params.force_fallback_adapter
+                                || info.device_type == wgpu::DeviceType::Cpu)
+                    })
+                    .min_by_key(|a| power_rank(a.get_info().device_type, params.power_preference))
+                    .ok_or(EngineError::NoAdapter)?
+            }
+            None => instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: params.power_preference,
+                    compatible_surface: surface,
+                    force_fallback_adapter: params.force_fallback_adapter,
+                })
+                .await
+                .ok_or(EngineError::NoAdapter)?,
+        };
+
+        let info = adapter.get_info();
+        log::info!("Using adapter {} ({:?})", info.name, info.backend);
+        Ok(adapter)
+    }
+
+    async fn request_device(
+        adapter: &wgpu::Adapter,
+        params: &EngineParams,
+        limits: wgpu::Limits,
+    ) -> Result<(wgpu::Device, wgpu::Queue), EngineError> {
+        Ok(adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    features: params.features,
+                    limits,
+                },
+                None,
+            )
+            .await?)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn from_parts(
+        event_loop: Option<EventLoop<()>>,
+        window: Option<Window>,
+        instance: wgpu::Instance,
+        surface: Option<wgpu::Surface>,
+        surface_conf: wgpu::SurfaceConfiguration,
+        adapter: wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        actions: ActionMap,
+    ) -> Self {
+        // Create input cache
+        let input = Input::new();
+
+        // Create the offscreen target when there is no surface to render to
+        let offscreen = match surface {
+            Some(_) => None,
+            None => Some(Offscreen::new(&device, &surface_conf)),
+        };
+
         // Create the renderer
-        let renderer = Renderer::new(&device, &surface_conf);
+        let renderer = Renderer::new(&device, &queue, &surface_conf);
 
         // Create default empty scene
         let scene = Scene::default();
@@ -116,20 +395,27 @@ impl Engine {
 
         // Create the camera
         let camera = Camera::new();
+        let controller = Box::new(FlyController);
+
+        // Create the frame clock
+        let clock = Clock::default();
 
         // Initialize supplemental engine state
         let state = EngineState {
             cursor_grabbed: false,
+            quit_requested: false,
         };
 
-        // Store objects
         Self {
-            event_loop: Some(event_loop),
+            event_loop,
             window,
             input,
+            actions,
+            gilrs: None,
             instance,
             surface,
             surface_conf,
+            offscreen,
             adapter,
             device,
             queue,
@@ -137,6 +423,8 @@ impl Engine {
             renderer_scene,
             scene,
             camera,
+            controller,
+            clock,
             state,
         }
     }
@@ -145,124 +433,442 @@ impl Engine {
         // Configure the surface with the new size
         self.surface_conf.width = size.0;
         self.surface_conf.height = size.1;
-        self.surface.configure(&self.device, &self.surface_conf);
+        match &self.surface {
+            Some(surface) => surface.configure(&self.device, &self.surface_conf),
+            None => self.offscreen = Some(Offscreen::new(&self.device, &self.surface_conf)),
+        }
         // Resize renderer resources
         self.renderer.resize(&self.device, &self.surface_conf);
     }
 
-    pub fn update(&mut self) {
-        let dt = 1.0 / 60.0; // TODO: Calculate this
+    pub fn update<A: App>(&mut self, app: &mut A) {
+        // Measure frame time and run the fixed step simulation
+        let steps = self.clock.tick();
+        self.simulate(app, steps);
+    }
 
-        let camkeys = [
-            (VirtualKeyCode::W, CameraMoveDirection::Forward),
-            (VirtualKeyCode::A, CameraMoveDirection::Left),
-            (VirtualKeyCode::S, CameraMoveDirection::Backward),
-            (VirtualKeyCode::D, CameraMoveDirection::Right),
-        ];
-        let dirs = camkeys
-            .iter()
-            .filter(|k| self.input.key_held(k.0))
-            .map(|k| k.1)
-            .collect::<Vec<_>>();
-        self.camera.move_to(&dirs, dt);
-
-        if self.state.cursor_grabbed {
-            let look_diff = self.input.mouse_diff();
-            self.camera.look(look_diff.into(), dt);
+    /// Like `update`, but advances by exactly one simulation step
+    /// instead of the measured frame time, for reproducible offscreen frames
+    pub fn update_fixed<A: App>(&mut self, app: &mut A) {
+        let steps = self.clock.advance(self.clock.step);
+        self.simulate(app, steps);
+    }
+
+    /// Feeds the input of the last frame to the controller and runs `steps` simulation steps
+    fn simulate<A: App>(&mut self, app: &mut A, steps: u32) {
+        let dt = self.clock.step;
+
+        let actions = self.actions.state(&self.input);
+        self.controller
+            .input(&mut self.camera, &actions, self.state.cursor_grabbed, dt);
+
+        for _ in 0..steps {
+            self.step(dt);
+            app.update(self, dt);
+        }
+        self.sync_renderer();
+    }
+
+    /// Uploads the camera, transform and light changes of the last update to the renderer
+    fn sync_renderer(&mut self) {
+        // Propagate transform changes made during the update
+        for id in self.scene.update_transforms() {
+            if let Some(object) = self.renderer_scene.objects.get_mut(&id) {
+                let world = self.scene.objects.get(id).unwrap().world;
+                self.renderer.update_transform(&self.queue, object, world);
+            }
         }
 
-        self.camera.update(dt);
-        self.scene.view = self.camera.matrix();
+        // Interpolate between the last two simulation steps
+        self.scene.view = self.camera.interpolated_matrix(self.clock.alpha);
         self.renderer_scene.view = self.scene.view;
+        if self.camera.projection != self.renderer.projection() {
+            self.renderer
+                .set_projection(&self.device, self.camera.projection);
+        }
+        self.renderer.update_lights(
+            &self.device,
+            &self.queue,
+            &self.renderer_scene,
+            &self.scene.lights,
+        );
     }
 
-    pub fn render(&self) {
-        // Acquire frame
-        let (surface, device, queue) = (&self.surface, &self.device, &self.queue);
-        let frame = surface
-            .get_current_texture()
-            .expect("Failed to acquire next surface texture");
+    /// Advances the simulation by a single fixed step of `dt` seconds
+    pub fn step(&mut self, dt: f32) {
+        let actions = self.actions.state(&self.input);
+        self.controller.step(&mut self.camera, &actions, dt);
+    }
 
-        // Create encoder
-        let encoder_desc = wgpu::CommandEncoderDescriptor { label: None };
-        let mut encoder = device.create_command_encoder(&encoder_desc);
+    /// Current state of the bound actions
+    pub fn action_state(&self) -> ActionState<'_> {
+        self.actions.state(&self.input)
+    }
+
+    /// Handles the engine's own actions of the last frame
+    fn handle_actions(&mut self) {
+        let actions = self.actions.state(&self.input);
+        let quit = actions.pressed("quit");
+        let screenshot = actions.pressed("screenshot");
+        let grab = actions.pressed("grab_cursor") && self.controller.grabs_cursor();
+        let release = actions.pressed("release_cursor");
+
+        if quit {
+            self.quit();
+        }
+        if screenshot {
+            let path = screenshot_path();
+            if let Err(e) = self.save_screenshot(&path) {
+                log::error!("{}", e);
+            }
+        }
+        if grab && !self.state.cursor_grabbed {
+            self.grab_cursor(true);
+        } else if release && self.state.cursor_grabbed {
+            self.grab_cursor(false);
+        }
+    }
+
+    /// Hands the camera over to `controller`, starting from its current placement
+    pub fn set_controller(&mut self, mut controller: Box<dyn CameraController>) {
+        if self.state.cursor_grabbed && !controller.grabs_cursor() {
+            self.grab_cursor(false);
+        }
+        controller.attach(&mut self.camera);
+        self.controller = controller;
+    }
+
+    /// Renders a frame to the surface, or to the offscreen target when headless.
+    ///
+    /// Lost or outdated surfaces are reconfigured and timed out frames are skipped,
+    /// only unrecoverable errors are returned
+    pub fn render(&self) -> Result<(), EngineError> {
+        let device = &self.device;
+
+        // Acquire frame, or render offscreen when headless
+        let frame = match &self.surface {
+            Some(surface) => match surface.get_current_texture() {
+                Ok(frame) => Some(frame),
+                Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                    // Reconfigure and try again on the next frame
+                    surface.configure(device, &self.surface_conf);
+                    return Ok(());
+                }
+                Err(wgpu::SurfaceError::Timeout) => {
+                    log::warn!("Timed out acquiring surface texture, skipping frame");
+                    return Ok(());
+                }
+                Err(wgpu::SurfaceError::OutOfMemory) => return Err(EngineError::OutOfMemory),
+            },
+            None => None,
+        };
 
         // Create output view
-        let view = frame
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        let frame_view = frame.as_ref().map(|frame| {
+            frame
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        });
+        let view = match (&frame_view, &self.offscreen) {
+            (Some(view), _) => view,
+            (None, Some(offscreen)) => &offscreen.view,
+            (None, None) => unreachable!("Engine has neither a surface nor an offscreen target"),
+        };
+
+        // Render and present
+        self.render_to(view);
+        if let Some(frame) = frame {
+            frame.present();
+        }
+        Ok(())
+    }
+
+    /// Renders the scene into `view` and submits the queue
+    fn render_to(&self, view: &wgpu::TextureView) {
+        // Create encoder
+        let encoder_desc = wgpu::CommandEncoderDescriptor { label: None };
+        let mut encoder = self.device.create_command_encoder(&encoder_desc);
 
         // Render and submit the queue
         self.renderer
-            .render(&mut encoder, queue, &view, &self.renderer_scene);
-        queue.submit(Some(encoder.finish()));
-        frame.present();
+            .render(&mut encoder, &self.queue, view, &self.renderer_scene);
+        self.queue.submit(Some(encoder.finish()));
     }
 
-    pub fn run(mut self) {
+    /// Renders the current frame and reads it back to the CPU as an RGBA image.
+    ///
+    /// Surface textures cannot be copied from, so windowed engines render
+    /// the frame again into a temporary target of the surface format
+    pub fn capture_frame(&self) -> Result<RgbaImage, EngineError> {
+        let temporary;
+        let target = match &self.offscreen {
+            Some(offscreen) => offscreen,
+            None => {
+                temporary = Offscreen::new(&self.device, &self.surface_conf);
+                &temporary
+            }
+        };
+        self.render_to(&target.view);
+        target.read(&self.device, &self.queue)
+    }
+
+    /// Captures the current frame and saves it as an image at `path`,
+    /// with the format picked by the extension
+    pub fn save_screenshot(&self, path: &Path) -> Result<(), EngineError> {
+        self.capture_frame()?.save(path)?;
+        log::info!("Saved screenshot {}", path.display());
+        Ok(())
+    }
+
+    /// Renders `path` at `fps` frames per second into numbered images in `dir`,
+    /// returning the number of frames saved. Fails if `fps` is not positive and finite
+    pub fn export_path(
+        &mut self,
+        path: &CameraPath,
+        fps: f32,
+        dir: &Path,
+    ) -> Result<u32, EngineError> {
+        if !(fps.is_finite() && fps > 0.0) {
+            return Err(EngineError::InvalidFrameRate(fps));
+        }
+        let frames = (path.duration() * fps).floor() as u32 + 1;
+        for frame in 0..frames {
+            // Place the camera exactly on the path, skipping interpolation
+            if let Some((position, rotation)) = path.sample(path.start() + frame as f32 / fps) {
+                self.camera.set_pose(position, rotation);
+            }
+            self.sync_renderer();
+            self.capture_frame()?
+                .save(dir.join(format!("frame{:05}.png", frame)))?;
+        }
+        log::info!("Saved {} frames to {}", frames, dir.display());
+        Ok(frames)
+    }
+
+    pub fn run<A: App + 'static>(mut self, mut app: A) {
         // Workaround the static lifetime requirements of event_loop
-        let event_loop = self.event_loop.take().unwrap();
+        let event_loop = self
+            .event_loop
+            .take()
+            .expect("Headless engines have no event loop to run");
+
+        // Let the application setup its state
+        app.init(&mut self);
+
+        // Don't count setup time towards the first frame
+        self.clock.reset();
 
         // Run the mainloop
         event_loop.run(move |event, _, control_flow| {
-            *control_flow = ControlFlow::Wait;
+            // Gamepad events do not wake the loop, so keep polling while any is connected
+            let gamepads = match &self.gilrs {
+                Some(gilrs) => gilrs.gamepads().next().is_some(),
+                None => false,
+            };
+            *control_flow = match gamepads {
+                true => ControlFlow::Poll,
+                false => ControlFlow::Wait,
+            };
             self.input.update(&event);
+            if let (Event::MainEventsCleared, Some(gilrs)) = (&event, &mut self.gilrs) {
+                self.input.update_gamepads(gilrs);
+            }
+            app.on_event(&mut self, &event);
             match event {
-                Event::WindowEvent { event, window_id } if window_id == self.window.id() => {
+                Event::WindowEvent { event, window_id } if window_id == self.window().id() => {
                     match event {
-                        WindowEvent::Resized(size) => self.resize(size.into()),
-                        WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                            self.resize((*new_inner_size).into())
+                        WindowEvent::Resized(size) => {
+                            self.resize(size.into());
+                            app.on_resize(&mut self, size.into());
                         }
-                        WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                        WindowEvent::MouseInput { button, state, .. }
-                            if state == ElementState::Pressed =>
-                        {
-                            match button {
-                                MouseButton::Left => {
-                                    self.window.set_cursor_grab(true).unwrap();
-                                    self.window.set_cursor_visible(false);
-                                    self.state.cursor_grabbed = true;
-                                }
-                                _ => (),
-                            }
+                        WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                            self.resize((*new_inner_size).into());
+                            app.on_resize(&mut self, (*new_inner_size).into());
                         }
-                        WindowEvent::KeyboardInput { input, .. } => match input {
-                            KeyboardInput {
-                                state: ElementState::Pressed,
-                                virtual_keycode: Some(VirtualKeyCode::Escape),
-                                ..
-                            } => *control_flow = ControlFlow::Exit,
-                            KeyboardInput {
-                                state: ElementState::Pressed,
-                                virtual_keycode: Some(VirtualKeyCode::RControl),
-                                ..
-                            } => {
-                                self.window.set_cursor_grab(false).unwrap();
-                                self.window.set_cursor_visible(true);
-                                self.state.cursor_grabbed = false;
-                            }
-                            _ => (),
-                        },
+                        WindowEvent::CloseRequested => self.quit(),
                         _ => (),
                     }
                 }
                 Event::RedrawRequested(_) => {
-                    self.update();
-                    self.render();
+                    self.handle_actions();
+                    self.update(&mut app);
+                    if let Err(e) = self.render() {
+                        log::error!("{}", e);
+                        self.quit();
+                    }
                 }
-                Event::MainEventsCleared => self.window.request_redraw(),
+                Event::MainEventsCleared => self.window().request_redraw(),
+                Event::LoopDestroyed => app.on_exit(&mut self),
                 _ => (),
             }
+
+            // Leave the mainloop when asked to
+            if self.state.quit_requested {
+                *control_flow = ControlFlow::Exit;
+            }
         });
     }
 
-    pub fn set_scene(&mut self, scene: Scene) {
+    /// Requests the main loop to exit after the current event
+    pub fn quit(&mut self) {
+        self.state.quit_requested = true;
+    }
+
+    /// Confines and hides the cursor for mouse look, or releases it
+    pub fn grab_cursor(&mut self, grab: bool) {
+        let window = self.window();
+        if let Err(e) = window.set_cursor_grab(grab) {
+            log::warn!("Failed to change cursor grab: {}", e);
+            return;
+        }
+        window.set_cursor_visible(!grab);
+        self.state.cursor_grabbed = grab;
+    }
+
+    fn window(&self) -> &Window {
+        self.window.as_ref().unwrap()
+    }
+
+    pub fn set_scene(&mut self, mut scene: Scene) {
+        scene.update_transforms();
+        if let Some(camera) = scene.camera {
+            self.set_camera_position(camera.position);
+            self.camera.look_at(camera.target);
+            if let Some(projection) = camera.projection {
+                self.camera.projection = projection;
+            }
+            self.controller.attach(&mut self.camera);
+        }
         self.scene = scene;
-        self.renderer_scene = self.renderer.create_scene(&self.device, &self.scene);
+        self.renderer_scene = self
+            .renderer
+            .create_scene(&self.device, &self.queue, &self.scene);
+    }
+
+    /// Replaces the scene with one loaded from a RON file
+    pub fn load_scene(&mut self, path: &Path) -> Result<(), SceneError> {
+        let scene = Scene::load(path)?;
+        self.set_scene(scene);
+        Ok(())
+    }
+
+    /// Saves the scene to a RON file, along with the current camera placement
+    pub fn save_scene(&mut self, path: &Path) -> Result<(), SceneError> {
+        self.scene.camera = Some(SceneCamera {
+            position: self.camera.position,
+            target: self.camera.position + self.camera.rotation * Vec3::Z,
+            projection: Some(self.camera.projection),
+        });
+        self.scene.save(path)
+    }
+
+    /// Adds `object` to the scene, uploading only its own resources.
+    /// Fails if its parent was removed
+    pub fn add_object(&mut self, object: SceneObject) -> Result<ObjectId, SceneError> {
+        let id = self.scene.add(object)?;
+        self.scene.update_transforms();
+        self.create_missing_objects();
+        Ok(id)
+    }
+
+    /// Adds the nodes of `model` under a new root object placed at `transform`.
+    /// Returns the id of the root object
+    pub fn add_model(&mut self, model: &Model, transform: Mat4) -> ObjectId {
+        let root = self.scene.add_model(model, transform);
+        self.create_missing_objects();
+        root
+    }
+
+    /// Creates the GPU resources of the scene objects that have none yet
+    fn create_missing_objects(&mut self) {
+        let renderer_objects = &mut self.renderer_scene.objects;
+        let (ids, objects): (Vec<_>, Vec<_>) = self
+            .scene
+            .objects
+            .iter()
+            .filter(|(id, _)| !renderer_objects.contains_key(id))
+            .unzip();
+        let gpu_objects = self
+            .renderer
+            .create_objects(&self.device, &self.queue, objects);
+        renderer_objects.extend(ids.into_iter().zip(gpu_objects));
+    }
+
+    /// Removes the object `id` from the scene and returns it.
+    /// Its children are attached to its parent, the ids of other objects are unaffected
+    pub fn remove_object(&mut self, id: ObjectId) -> Result<SceneObject, SceneError> {
+        let object = self.scene.remove(id)?;
+        self.renderer_scene.objects.remove(&id);
+        Ok(object)
+    }
+
+    /// Attaches the object `id` to `parent`.
+    /// Fails if either object was removed or if that would create a cycle.
+    /// World transforms are refreshed on the next update
+    pub fn set_parent(&mut self, id: ObjectId, parent: Option<ObjectId>) -> Result<(), SceneError> {
+        self.scene.set_parent(id, parent)
+    }
+
+    /// Sets the local transform of object `id`.
+    /// It and its descendants are moved on the next update
+    pub fn update_transform(&mut self, id: ObjectId, transform: Mat4) -> Result<(), SceneError> {
+        self.scene.object_mut(id)?.transform = Transform::from_matrix(transform);
+        Ok(())
+    }
+
+    /// Changes the material of the `index`th mesh of object `id`,
+    /// only recreating its material binding.
+    /// Fails if the object was removed or has no such mesh
+    pub fn set_material(
+        &mut self,
+        id: ObjectId,
+        index: usize,
+        material: Option<Material>,
+    ) -> Result<(), SceneError> {
+        let object = self.scene.object_mut(id)?;
+        if index >= object.materials.len() {
+            return Err(SceneError::InvalidMaterialIndex(id, index));
+        }
+        let gpu_object = self
+            .renderer_scene
+            .objects
+            .get_mut(&id)
+            .ok_or(SceneError::UnknownObject(id))?;
+        self.renderer.update_material(
+            &self.device,
+            &self.queue,
+            gpu_object,
+            index,
+            material.as_ref(),
+        );
+        object.materials[index] = material;
+        Ok(())
     }
 
     pub fn set_camera_position(&mut self, position: Vec3) {
         self.camera.set_position(position);
     }
 }
+
+/// How well an adapter of `device_type` suits `preference`, lower is better
+fn power_rank(device_type: wgpu::DeviceType, preference: wgpu::PowerPreference) -> u8 {
+    use wgpu::{DeviceType::*, PowerPreference::*};
+    match (preference, device_type) {
+        (HighPerformance, DiscreteGpu) | (LowPower, IntegratedGpu) => 0,
+        (_, DiscreteGpu) | (_, IntegratedGpu) => 1,
+        (_, VirtualGpu) | (_, Other) => 2,
+        (_, Cpu) => 3,
+    }
+}
+
+/// Timestamped screenshot file name in the working directory
+fn screenshot_path() -> PathBuf {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    PathBuf::from(format!(
+        "screenshot-{}-{:03}.png",
+        now.as_secs(),
+        now.subsec_millis()
+    ))
+}
@@ -0,0 +1,84 @@
+//
+// error.rs
+//
+
+use std::{error::Error, fmt};
+
+/// Errors reported by the Engine
+#[derive(Debug)]
+pub enum EngineError {
+    /// The window could not be created
+    WindowCreation(winit::error::OsError),
+    /// No adapter satisfying the requested options was found
+    NoAdapter,
+    /// The logical device could not be created
+    RequestDevice(wgpu::RequestDeviceError),
+    /// The surface cannot be presented by the selected adapter
+    IncompatibleSurface,
+    /// Reading back a rendered frame failed
+    Readback(wgpu::BufferAsyncError),
+    /// Frames of this format cannot be read back
+    UnsupportedFormat(wgpu::TextureFormat),
+    /// A captured frame could not be saved
+    Screenshot(image::ImageError),
+    /// The GPU ran out of memory
+    OutOfMemory,
+    /// A frame rate that is not positive and finite
+    InvalidFrameRate(f32),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::WindowCreation(e) => write!(f, "Failed to create window: {}", e),
+            EngineError::NoAdapter => write!(f, "Failed to find an appropriate adapter"),
+            EngineError::RequestDevice(e) => write!(f, "Failed to create device: {}", e),
+            EngineError::IncompatibleSurface => {
+                write!(f, "Surface is incompatible with the adapter")
+            }
+            EngineError::Readback(e) => write!(f, "Failed to read back frame: {}", e),
+            EngineError::UnsupportedFormat(format) => {
+                write!(f, "Cannot read back frames of format {:?}", format)
+            }
+            EngineError::Screenshot(e) => write!(f, "Failed to save screenshot: {}", e),
+            EngineError::OutOfMemory => write!(f, "Out of GPU memory"),
+            EngineError::InvalidFrameRate(fps) => write!(f, "Invalid frame rate {}", fps),
+        }
+    }
+}
+
+impl Error for EngineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EngineError::WindowCreation(e) => Some(e),
+            EngineError::RequestDevice(e) => Some(e),
+            EngineError::Readback(e) => Some(e),
+            EngineError::Screenshot(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<winit::error::OsError> for EngineError {
+    fn from(e: winit::error::OsError) -> Self {
+        EngineError::WindowCreation(e)
+    }
+}
+
+impl From<wgpu::RequestDeviceError> for EngineError {
+    fn from(e: wgpu::RequestDeviceError) -> Self {
+        EngineError::RequestDevice(e)
+    }
+}
+
+impl From<wgpu::BufferAsyncError> for EngineError {
+    fn from(e: wgpu::BufferAsyncError) -> Self {
+        EngineError::Readback(e)
+    }
+}
+
+impl From<image::ImageError> for EngineError {
+    fn from(e: image::ImageError) -> Self {
+        EngineError::Screenshot(e)
+    }
+}
@@ -0,0 +1,225 @@
+//
+// gltf_import.rs
+//
+
+use super::{
+    mesh::{Mesh, Vertex},
+    model::{ImportOptions, Material, MaterialMaps, Model, ModelError, ModelNode, Texture},
+};
+use glam::{Mat4, Vec3};
+use image::{DynamicImage, ImageBuffer, RgbaImage};
+use std::{collections::HashMap, ffi::OsStr, path::Path, sync::Arc};
+
+impl Model {
+    /// Imports a glTF 2.0 file, either `.gltf` with its buffers or a binary `.glb`.
+    ///
+    /// Every triangle primitive becomes a Mesh, and the node hierarchy
+    /// of the default scene is kept in ModelNodes
+    pub fn from_gltf(path: &Path, options: &ImportOptions) -> Result<Self, ModelError> {
+        let (doc, buffers, images) = gltf::import(path).map_err(|error| ModelError::Gltf {
+            path: path.into(),
+            error,
+        })?;
+        let name = path.file_stem().and_then(OsStr::to_str).unwrap_or_default();
+
+        // glTF is right handed, mirror Z like the OBJ loader does
+        let flip = Vec3::new(1.0, 1.0, -1.0);
+
+        // Load materials with unique names, as meshes refer to them by name.
+        // Images are converted once and shared between materials
+        let mut textures: HashMap<usize, Arc<Texture>> = HashMap::new();
+        let mut materials: Vec<Material> = vec![];
+        for m in doc.materials() {
+            let index = m.index().unwrap_or_default();
+            let mut name = m
+                .name()
+                .map_or_else(|| format!("material{}", index), str::to_owned);
+            if materials.iter().any(|x| x.name == name) {
+                name = format!("{}.{}", name, index);
+            }
+
+            let mut texture = |t: gltf::Texture| {
+                let source = t.source();
+                let texture = textures.entry(source.index()).or_insert_with(|| {
+                    Arc::new(Texture {
+                        name: source
+                            .name()
+                            .map_or_else(|| format!("image{}", source.index()), str::to_owned),
+                        image: convert_image(&images[source.index()]),
+                    })
+                });
+                texture.clone()
+            };
+
+            let pbr = m.pbr_metallic_roughness();
+            let normal = m.normal_texture();
+            let occlusion = m.occlusion_texture();
+            let maps = MaterialMaps {
+                base_color: pbr.base_color_texture().map(|i| texture(i.texture())),
+                metallic_roughness: pbr
+                    .metallic_roughness_texture()
+                    .map(|i| texture(i.texture())),
+                normal: normal.as_ref().map(|n| texture(n.texture())),
+                occlusion: occlusion.as_ref().map(|o| texture(o.texture())),
+                emissive: m.emissive_texture().map(|i| texture(i.texture())),
+            };
+
+            // Only blended materials are translucent
+            let [r, g, b, a] = pbr.base_color_factor();
+            let dissolve = match m.alpha_mode() {
+                gltf::material::AlphaMode::Blend => a,
+                _ => 1.0,
+            };
+
+            materials.push(Material {
+                name,
+                base_color: Vec3::new(r, g, b),
+                metallic: pbr.metallic_factor(),
+                roughness: pbr.roughness_factor(),
+                emissive: m.emissive_factor().into(),
+                normal_scale: normal.map_or(1.0, |n| n.scale()),
+                dissolve,
+                occlusion_strength: occlusion.map_or(1.0, |o| o.strength()),
+                maps,
+                ..Default::default()
+            });
+        }
+
+        // Load every primitive as a separate mesh
+        let mut meshes = vec![];
+        let mut mesh_materials = vec![];
+        let mut primitive_meshes = vec![];
+        for mesh in doc.meshes() {
+            let mut indices = vec![];
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    log::warn!("Skipping non triangle primitive of mesh {}", mesh.index());
+                    continue;
+                }
+
+                let reader = primitive.reader(|b| Some(&buffers[b.index()]));
+                let mut vertices: Vec<Vertex> = match reader.read_positions() {
+                    Some(positions) => positions
+                        .map(|p| Vertex::new(Vec3::from(p) * flip))
+                        .collect(),
+                    None => continue,
+                };
+                let elems: Vec<u32> = match reader.read_indices() {
+                    Some(elems) => elems.into_u32().collect(),
+                    None => (0..vertices.len() as u32).collect(),
+                };
+
+                // Mirroring flips the winding, restore it
+                let elems = elems
+                    .chunks_exact(3)
+                    .flat_map(|i| [i[2], i[1], i[0]])
+                    .collect();
+
+                if let Some(uvs) = reader.read_tex_coords(0) {
+                    for (v, uv) in vertices.iter_mut().zip(uvs.into_f32()) {
+                        v.uv = uv.into();
+                    }
+                }
+
+                let normals = reader.read_normals();
+                let has_normals = normals.is_some();
+                if let Some(normals) = normals {
+                    for (v, n) in vertices.iter_mut().zip(normals) {
+                        v.normal = Vec3::from(n) * flip;
+                    }
+                }
+
+                let mut m = Mesh {
+                    vertices,
+                    indices: elems,
+                };
+                if !has_normals || options.regenerate_normals {
+                    m.generate_normals_with(options.normals);
+                }
+
+                indices.push(meshes.len());
+                meshes.push(m);
+                mesh_materials.push(
+                    primitive
+                        .material()
+                        .index()
+                        .map(|i| materials[i].name.clone()),
+                );
+            }
+            primitive_meshes.push(indices);
+        }
+
+        // Collect the node hierarchy of the scene
+        let mut nodes = vec![];
+        let mirror = Mat4::from_scale(flip);
+        if let Some(scene) = doc.default_scene().or_else(|| doc.scenes().next()) {
+            for node in scene.nodes() {
+                collect_nodes(&node, None, &primitive_meshes, &mut nodes);
+            }
+        }
+        for node in &mut nodes {
+            node.transform = mirror * node.transform * mirror;
+        }
+
+        Ok(Model {
+            name: name.to_owned(),
+            path: Some(path.into()),
+            options: *options,
+            meshes,
+            materials,
+            mesh_materials,
+            nodes,
+        })
+    }
+}
+
+/// Appends `node` and its descendants to `nodes`, parents first
+fn collect_nodes(
+    node: &gltf::Node,
+    parent: Option<usize>,
+    primitive_meshes: &[Vec<usize>],
+    nodes: &mut Vec<ModelNode>,
+) {
+    let index = nodes.len();
+    nodes.push(ModelNode {
+        name: node
+            .name()
+            .map_or_else(|| format!("node{}", node.index()), str::to_owned),
+        parent,
+        transform: Mat4::from_cols_array_2d(&node.transform().matrix()),
+        meshes: node
+            .mesh()
+            .map(|m| primitive_meshes[m.index()].clone())
+            .unwrap_or_default(),
+    });
+    for child in node.children() {
+        collect_nodes(&child, Some(index), primitive_meshes, nodes);
+    }
+}
+
+/// Converts decoded glTF image data of any format to RGBA
+fn convert_image(data: &gltf::image::Data) -> RgbaImage {
+    use gltf::image::Format;
+
+    let (w, h) = (data.width, data.height);
+    let bytes = data.pixels.clone();
+    let words = || {
+        data.pixels
+            .chunks_exact(2)
+            .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+            .collect::<Vec<_>>()
+    };
+    let image = match data.format {
+        Format::R8 => ImageBuffer::from_raw(w, h, bytes).map(DynamicImage::ImageLuma8),
+        Format::R8G8 => ImageBuffer::from_raw(w, h, bytes).map(DynamicImage::ImageLumaA8),
+        Format::R8G8B8 => ImageBuffer::from_raw(w, h, bytes).map(DynamicImage::ImageRgb8),
+        Format::R8G8B8A8 => ImageBuffer::from_raw(w, h, bytes).map(DynamicImage::ImageRgba8),
+        Format::B8G8R8 => ImageBuffer::from_raw(w, h, bytes).map(DynamicImage::ImageBgr8),
+        Format::B8G8R8A8 => ImageBuffer::from_raw(w, h, bytes).map(DynamicImage::ImageBgra8),
+        Format::R16 => ImageBuffer::from_raw(w, h, words()).map(DynamicImage::ImageLuma16),
+        Format::R16G16 => ImageBuffer::from_raw(w, h, words()).map(DynamicImage::ImageLumaA16),
+        Format::R16G16B16 => ImageBuffer::from_raw(w, h, words()).map(DynamicImage::ImageRgb16),
+        Format::R16G16B16A16 => ImageBuffer::from_raw(w, h, words()).map(DynamicImage::ImageRgba16),
+    };
+    image.expect("Image data matches its dimensions").to_rgba8()
+}
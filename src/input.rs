@@ -1,18 +1,33 @@
 //
 // input.rs
 //
-use winit::event::{DeviceEvent, ElementState, Event, MouseButton, VirtualKeyCode, WindowEvent};
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+use std::collections::{HashMap, HashSet};
+use winit::event::{
+    DeviceEvent, ElementState, Event, ModifiersState, MouseButton, MouseScrollDelta,
+    VirtualKeyCode, WindowEvent,
+};
+
+/// Pixels of touchpad scrolling counted as one scroll wheel line
+const PIXELS_PER_LINE: f32 = 20.0;
 
 /// Input state helper
 ///
 /// Call `Input::update` for every `winit::event::Event` you receive from winit.
 /// `Input::update` returning true indicates a step has occured.
+/// Gamepads are polled separately with `Input::update_gamepads`,
+/// and queries about them consider every connected gamepad
 pub struct Input {
     mouse_actions: Vec<MouseAction>,
     key_actions: Vec<KeyAction>,
+    gamepad_actions: Vec<GamepadAction>,
+    gamepads: HashMap<GamepadId, GamepadState>,
+    dead_zones: DeadZones,
     key_held: [bool; 255],
     mouse_held: [bool; 255],
     mouse_delta: Option<(f32, f32)>,
+    scroll_delta: f32,
+    modifiers: ModifiersState,
     cursor_point: Option<(f32, f32)>,
     cursor_point_prev: Option<(f32, f32)>,
 }
@@ -29,15 +44,47 @@ pub enum MouseAction {
     Released(MouseButton),
 }
 
+#[derive(Clone)]
+pub enum GamepadAction {
+    Connected(GamepadId),
+    Disconnected(GamepadId),
+    Pressed(GamepadId, Button),
+    Released(GamepadId, Button),
+}
+
+/// Gamepad values below which input is ignored, as a fraction of the full range
+///
+/// Values past the dead zone are rescaled to start from zero
+#[derive(Copy, Clone, Debug)]
+pub struct DeadZones {
+    /// Applied to the distance of each stick from its center
+    pub stick: f32,
+    /// Applied to the other axes and to analog buttons like triggers
+    pub axis: f32,
+}
+
+/// Raw values of a connected gamepad
+#[derive(Default)]
+struct GamepadState {
+    held: HashSet<Button>,
+    buttons: HashMap<Button, f32>,
+    axes: HashMap<Axis, f32>,
+}
+
 #[allow(dead_code)]
 impl Input {
     pub fn new() -> Input {
         Input {
             mouse_actions: vec![],
             key_actions: vec![],
+            gamepad_actions: vec![],
+            gamepads: HashMap::new(),
+            dead_zones: DeadZones::default(),
             key_held: [false; 255],
             mouse_held: [false; 255],
             mouse_delta: None,
+            scroll_delta: 0.0,
+            modifiers: ModifiersState::empty(),
             cursor_point: None,
             cursor_point_prev: None,
         }
@@ -46,7 +93,9 @@ impl Input {
     fn step(&mut self) {
         self.mouse_actions = vec![];
         self.key_actions = vec![];
+        self.gamepad_actions = vec![];
         self.mouse_delta = None;
+        self.scroll_delta = 0.0;
         self.cursor_point_prev = self.cursor_point;
     }
 
@@ -76,9 +125,16 @@ impl Input {
                     self.mouse_actions.push(MouseAction::Released(*button));
                 }
             },
+            WindowEvent::ModifiersChanged(modifiers) => self.modifiers = *modifiers,
             WindowEvent::CursorMoved { position, .. } => {
                 self.cursor_point = Some((position.x as _, position.y as _));
             }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll_delta += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(p) => p.y as f32 / PIXELS_PER_LINE,
+                };
+            }
             _ => (),
         }
     }
@@ -117,6 +173,50 @@ impl Input {
         }
     }
 
+    /// Drains the pending events of `gilrs`, call this once per step after the winit events
+    pub fn update_gamepads(&mut self, gilrs: &mut Gilrs) {
+        while let Some(event) = gilrs.next_event() {
+            let id = event.id;
+            match event.event {
+                EventType::Connected => {
+                    log::info!("Gamepad connected: {}", gilrs.gamepad(id).name());
+                    self.gamepads.insert(id, GamepadState::default());
+                    self.gamepad_actions.push(GamepadAction::Connected(id));
+                }
+                EventType::Disconnected => {
+                    log::info!("Gamepad disconnected: {}", gilrs.gamepad(id).name());
+                    self.gamepads.remove(&id);
+                    self.gamepad_actions.push(GamepadAction::Disconnected(id));
+                }
+                EventType::ButtonPressed(button, _) => {
+                    self.gamepads.entry(id).or_default().held.insert(button);
+                    self.gamepad_actions
+                        .push(GamepadAction::Pressed(id, button));
+                }
+                EventType::ButtonReleased(button, _) => {
+                    self.gamepads.entry(id).or_default().held.remove(&button);
+                    self.gamepad_actions
+                        .push(GamepadAction::Released(id, button));
+                }
+                EventType::ButtonChanged(button, value, _) => {
+                    self.gamepads
+                        .entry(id)
+                        .or_default()
+                        .buttons
+                        .insert(button, value);
+                }
+                EventType::AxisChanged(axis, value, _) => {
+                    self.gamepads
+                        .entry(id)
+                        .or_default()
+                        .axes
+                        .insert(axis, value);
+                }
+                _ => (),
+            }
+        }
+    }
+
     /// Returns true when the specified keyboard key goes from "not pressed" to "pressed"
     /// Otherwise returns false
     pub fn key_pressed(&self, key_code: VirtualKeyCode) -> bool {
@@ -187,6 +287,85 @@ impl Input {
         self.mouse_delta.unwrap_or((0.0, 0.0))
     }
 
+    /// Returns the scroll wheel lines moved during the last step, positive away from the user
+    pub fn scroll_diff(&self) -> f32 {
+        self.scroll_delta
+    }
+
+    /// Returns the modifier keys currently held
+    pub fn modifiers(&self) -> ModifiersState {
+        self.modifiers
+    }
+
+    /// Returns true when the specified button of any gamepad goes from "not pressed" to "pressed"
+    /// Otherwise returns false
+    pub fn gamepad_pressed(&self, button: Button) -> bool {
+        self.gamepad_actions
+            .iter()
+            .any(|a| matches!(a, &GamepadAction::Pressed(_, b) if b == button))
+    }
+
+    /// Returns true when the specified button of any gamepad goes from "pressed" to "not pressed"
+    /// Otherwise returns false
+    pub fn gamepad_released(&self, button: Button) -> bool {
+        self.gamepad_actions
+            .iter()
+            .any(|a| matches!(a, &GamepadAction::Released(_, b) if b == button))
+    }
+
+    /// Returns true while the specified button of any gamepad remains "pressed"
+    /// Otherwise returns false
+    pub fn gamepad_held(&self, button: Button) -> bool {
+        self.gamepads.values().any(|g| g.held.contains(&button))
+    }
+
+    /// Returns the value of an analog button like a trigger in the range 0 to 1,
+    /// the largest one among the gamepads, past the axis dead zone
+    pub fn gamepad_button_value(&self, button: Button) -> f32 {
+        let value = self
+            .gamepads
+            .values()
+            .map(|g| g.buttons.get(&button).copied().unwrap_or(0.0))
+            .fold(0.0, f32::max);
+        dead_zone(value, self.dead_zones.axis)
+    }
+
+    /// Returns the value of an axis in the range -1 to 1, the one furthest from the center
+    /// among the gamepads, past the dead zones. Sticks use a radial dead zone
+    pub fn gamepad_axis(&self, axis: Axis) -> f32 {
+        self.gamepads
+            .values()
+            .map(|g| g.axis(axis, &self.dead_zones))
+            .fold(0.0, |a, v| if v.abs() > a.abs() { v } else { a })
+    }
+
+    /// Returns true when a gamepad got connected during the last step
+    pub fn gamepad_connected(&self) -> bool {
+        self.gamepad_actions
+            .iter()
+            .any(|a| matches!(a, GamepadAction::Connected(_)))
+    }
+
+    /// Returns true when a gamepad got disconnected during the last step
+    pub fn gamepad_disconnected(&self) -> bool {
+        self.gamepad_actions
+            .iter()
+            .any(|a| matches!(a, GamepadAction::Disconnected(_)))
+    }
+
+    /// Returns the number of connected gamepads that sent any input
+    pub fn gamepad_count(&self) -> usize {
+        self.gamepads.len()
+    }
+
+    pub fn dead_zones(&self) -> DeadZones {
+        self.dead_zones
+    }
+
+    pub fn set_dead_zones(&mut self, dead_zones: DeadZones) {
+        self.dead_zones = dead_zones;
+    }
+
     /// Returns `None` when the cursor is outside of the window.
     /// Otherwise returns the cursor coordinates in pixels
     pub fn cursor(&self) -> Option<(f32, f32)> {
@@ -203,6 +382,50 @@ impl Input {
     }
 }
 
+impl Default for Input {
+    fn default() -> Self {
+        Input::new()
+    }
+}
+
+impl Default for DeadZones {
+    fn default() -> Self {
+        DeadZones {
+            stick: 0.15,
+            axis: 0.05,
+        }
+    }
+}
+
+impl GamepadState {
+    fn axis(&self, axis: Axis, dead_zones: &DeadZones) -> f32 {
+        let value = |axis| self.axes.get(&axis).copied().unwrap_or(0.0);
+        let partner = match axis {
+            Axis::LeftStickX => Axis::LeftStickY,
+            Axis::LeftStickY => Axis::LeftStickX,
+            Axis::RightStickX => Axis::RightStickY,
+            Axis::RightStickY => Axis::RightStickX,
+            _ => return value(axis).signum() * dead_zone(value(axis).abs(), dead_zones.axis),
+        };
+
+        // Scale both stick axes together to keep the direction of the stick
+        let (v, p) = (value(axis), value(partner));
+        let length = (v * v + p * p).sqrt();
+        match length > 0.0 {
+            true => v / length * dead_zone(length, dead_zones.stick),
+            false => 0.0,
+        }
+    }
+}
+
+/// Rescales `value` in the range 0 to 1 to start from zero past `dead_zone`
+fn dead_zone(value: f32, dead_zone: f32) -> f32 {
+    match value > dead_zone {
+        true => ((value - dead_zone) / (1.0 - dead_zone)).min(1.0),
+        false => 0.0,
+    }
+}
+
 fn mouse_button_to_int(button: MouseButton) -> usize {
     match button {
         MouseButton::Left => 0,
@@ -211,3 +434,63 @@ fn mouse_button_to_int(button: MouseButton) -> usize {
         MouseButton::Other(byte) => byte as usize,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::assert_close;
+
+    #[test]
+    fn dead_zone_rescales_past_threshold() {
+        assert_eq!(dead_zone(0.1, 0.2), 0.0);
+        assert_eq!(dead_zone(0.2, 0.2), 0.0);
+        assert_close(dead_zone(0.6, 0.2), 0.5);
+        assert_eq!(dead_zone(1.0, 0.2), 1.0);
+        assert_eq!(dead_zone(1.5, 0.2), 1.0);
+    }
+
+    #[test]
+    fn stick_dead_zone_is_radial() {
+        let dead_zones = DeadZones {
+            stick: 0.2,
+            axis: 0.1,
+        };
+        let stick = |x: f32, y: f32| GamepadState {
+            axes: [(Axis::LeftStickX, x), (Axis::LeftStickY, y)]
+                .iter()
+                .copied()
+                .collect(),
+            ..Default::default()
+        };
+
+        // Each axis alone is past the dead zone, but the stick is not
+        let inside = stick(0.12, -0.12);
+        assert_eq!(inside.axis(Axis::LeftStickX, &dead_zones), 0.0);
+        assert_eq!(inside.axis(Axis::LeftStickY, &dead_zones), 0.0);
+
+        // Past it both axes shrink together, keeping the direction
+        let outside = stick(0.36, -0.48);
+        let (x, y) = (
+            outside.axis(Axis::LeftStickX, &dead_zones),
+            outside.axis(Axis::LeftStickY, &dead_zones),
+        );
+        assert_close((x * x + y * y).sqrt(), 0.5);
+        assert_close(x / y, 0.36 / -0.48);
+
+        // Other sticks are unaffected
+        assert_eq!(outside.axis(Axis::RightStickX, &dead_zones), 0.0);
+    }
+
+    #[test]
+    fn other_axes_use_their_own_dead_zone() {
+        let dead_zones = DeadZones {
+            stick: 0.5,
+            axis: 0.1,
+        };
+        let state = GamepadState {
+            axes: [(Axis::LeftZ, -0.55)].iter().copied().collect(),
+            ..Default::default()
+        };
+        assert_close(state.axis(Axis::LeftZ, &dead_zones), -0.5);
+    }
+}
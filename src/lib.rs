@@ -0,0 +1,34 @@
+//
+// lib.rs
+//
+
+#[macro_use]
+mod shader;
+pub mod action;
+pub mod app;
+pub mod camera;
+pub mod camera_path;
+pub mod clock;
+pub mod config;
+pub mod controller;
+pub mod engine;
+pub mod error;
+pub mod geometry;
+mod gltf_import;
+pub mod input;
+pub mod light;
+pub mod mesh;
+pub mod model;
+pub mod offscreen;
+pub mod renderer;
+pub mod scene;
+mod scene_file;
+pub mod slot_map;
+#[cfg(test)]
+mod test_util;
+pub mod uniform;
+
+pub use app::App;
+pub use config::EngineConfig;
+pub use engine::{Engine, EngineParams, FullscreenMode, WindowParams};
+pub use error::EngineError;
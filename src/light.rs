@@ -0,0 +1,111 @@
+//
+// light.rs
+//
+
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+/// Shortest range of point and spot lights. Shorter ranges, including
+/// non positive ones, are clamped to it however the light is added
+pub const MIN_RANGE: f32 = 0.1;
+
+/// Light source placed in world space
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: Vec3,
+    pub intensity: f32,
+    /// Distance at which the light fades out completely, unused by directional lights
+    pub range: f32,
+    /// Shadow map settings, lights without them cast no shadows
+    #[serde(default)]
+    pub shadow: Option<Shadow>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LightKind {
+    /// Infinitely far away light shining along `direction`
+    Directional { direction: Vec3 },
+    /// Light shining equally in all directions
+    Point { position: Vec3 },
+    /// Light shining in a cone along `direction`, fading out
+    /// between the inner and outer half angles in radians
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+/// Shadow map settings of a light
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Shadow {
+    /// Width and height of the shadow map in texels
+    pub resolution: u32,
+    /// Depth bias against shadow acne
+    pub bias: f32,
+    /// Extra depth bias for surfaces at grazing angles to the light
+    pub slope_bias: f32,
+}
+
+impl Light {
+    pub fn directional(direction: Vec3) -> Self {
+        Self::white(LightKind::Directional { direction }, 0.0)
+    }
+
+    pub fn point(position: Vec3, range: f32) -> Self {
+        Self::white(LightKind::Point { position }, range)
+    }
+
+    pub fn spot(position: Vec3, direction: Vec3, outer_angle: f32, range: f32) -> Self {
+        let kind = LightKind::Spot {
+            position,
+            direction,
+            inner_angle: outer_angle * 0.8,
+            outer_angle,
+        };
+        Self::white(kind, range)
+    }
+
+    pub fn with_color(self, color: Vec3, intensity: f32) -> Self {
+        Light {
+            color,
+            intensity,
+            ..self
+        }
+    }
+
+    pub fn with_shadow(self, shadow: Shadow) -> Self {
+        Light {
+            shadow: Some(shadow),
+            ..self
+        }
+    }
+
+    /// Range used for lighting and shadows, at least `MIN_RANGE`
+    pub fn clamped_range(&self) -> f32 {
+        self.range.max(MIN_RANGE)
+    }
+
+    fn white(kind: LightKind, range: f32) -> Self {
+        Light {
+            kind,
+            color: Vec3::ONE,
+            intensity: 1.0,
+            range,
+            shadow: None,
+        }
+    }
+}
+
+impl Default for Shadow {
+    fn default() -> Self {
+        Shadow {
+            resolution: 1024,
+            bias: 0.0005,
+            slope_bias: 0.005,
+        }
+    }
+}
@@ -2,99 +2,319 @@
 // main.rs
 //
 
-extern crate log;
-
-#[macro_use]
-mod shader;
-mod camera;
-mod engine;
-mod geometry;
-mod input;
-mod mesh;
-mod model;
-mod renderer;
-mod scene;
-mod uniform;
-
-use engine::{Engine, EngineParams, WindowParams};
-use geometry::Centered;
 use glam::{Mat4, Vec3};
-use mesh::{Index, Mesh, Vertex};
-use model::Model;
-use scene::{Scene, SceneObject};
-
-#[allow(dead_code)]
-fn demo_mesh() -> Mesh {
-    use genmesh::{
-        generators::{IcoSphere, IndexedPolygon, SharedVertex},
-        Triangulate,
-    };
+use natsukashii::{
+    action::Binding,
+    camera_path::CameraPath,
+    config::parse_size,
+    controller::{
+        CameraController, FlyController, OrbitController, PathController, TurntableController,
+    },
+    light::Light,
+    mesh::NormalMode,
+    model::{ImportOptions, Model},
+    scene::{Scene, SceneCamera},
+    App, Engine, EngineConfig, EngineParams,
+};
+use std::{error::Error, ffi::OsStr, fs, path::PathBuf};
+use winit::event::{Event, VirtualKeyCode};
+
+const USAGE: &str = "\
+Usage: natsukashii <model.obj|model.gltf|model.glb|scene.ron> [options]
+
+Options:
+    --camera X,Y,Z      Initial camera position
+    --target X,Y,Z      Initial point to look at
+    --controller NAME   Camera controller: fly, orbit or turntable around the target
+    --size WxH          Window size
+    --normals MODE      Regenerate model normals: flat, area or angle weighted
+    --crease DEGREES    Keep edges between faces meeting at wider angles hard
+    --config FILE       Engine config file
+    --screenshot FILE   Render headless and save the last frame to FILE
+    --frames N          Frames to render before taking the screenshot, one simulation step each
+    --record FILE       Save a camera path keyframe on every record_keyframe (K) press to FILE
+    --play FILE         Play back a camera path, looping
+    --export DIR        Render the played path headless into numbered images in DIR
+    --fps N             Frame rate of the exported path";
+
+/// Viewer command line arguments
+struct Args {
+    model: PathBuf,
+    camera: Option<Vec3>,
+    target: Option<Vec3>,
+    controller: String,
+    size: Option<(u32, u32)>,
+    import: ImportOptions,
+    config: Option<PathBuf>,
+    screenshot: Option<PathBuf>,
+    frames: u32,
+    record: Option<PathBuf>,
+    play: Option<PathBuf>,
+    export: Option<PathBuf>,
+    fps: f32,
+}
+
+/// Records camera path keyframes while viewing
+struct Recorder {
+    file: PathBuf,
+    path: CameraPath,
+    time: f32,
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut args = std::env::args().skip(1);
+        let mut model = None;
+        let mut parsed = Args {
+            model: PathBuf::new(),
+            camera: None,
+            target: None,
+            controller: "fly".to_owned(),
+            size: None,
+            import: ImportOptions::default(),
+            config: None,
+            screenshot: None,
+            frames: 1,
+            record: None,
+            play: None,
+            export: None,
+            fps: 30.0,
+        };
 
-    let prim = IcoSphere::subdivide(1);
-    let vertices: Vec<Vertex> = prim
-        .shared_vertex_iter()
-        .map(|v| Vertex::new(v.pos.into()))
-        .collect();
-    let indices: Vec<Index> = prim
-        .indexed_polygon_iter()
-        .triangulate()
-        .map(|f| vec![f.x as _, f.y as _, f.z as _])
-        .flatten()
-        .collect();
-
-    let mut mesh = Mesh { vertices, indices };
-    mesh.generate_normals();
-    mesh
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+            match arg.as_str() {
+                "--camera" => parsed.camera = Some(parse_vec3(&value()?)?),
+                "--target" => parsed.target = Some(parse_vec3(&value()?)?),
+                "--controller" => match value()?.as_str() {
+                    v @ ("fly" | "orbit" | "turntable") => parsed.controller = v.to_owned(),
+                    v => return Err(format!("Unknown controller {:?}", v)),
+                },
+                "--size" => {
+                    let v = value()?;
+                    let size = parse_size(&v).ok_or_else(|| format!("Invalid size {:?}", v))?;
+                    parsed.size = Some(size);
+                }
+                "--normals" => {
+                    let v = value()?;
+                    parsed.import.regenerate_normals = true;
+                    parsed.import.normals.mode = match v.as_str() {
+                        "flat" => NormalMode::Flat,
+                        "area" => NormalMode::Area,
+                        "angle" => NormalMode::Angle,
+                        _ => return Err(format!("Invalid normals mode {:?}", v)),
+                    };
+                }
+                "--crease" => {
+                    let v = value()?;
+                    let degrees: f32 = v.parse().map_err(|_| format!("Invalid angle {:?}", v))?;
+                    parsed.import.normals.crease_angle = Some(degrees.to_radians());
+                }
+                "--config" => parsed.config = Some(value()?.into()),
+                "--screenshot" => parsed.screenshot = Some(value()?.into()),
+                "--frames" => {
+                    let v = value()?;
+                    parsed.frames = v.parse().map_err(|_| format!("Invalid frames {:?}", v))?;
+                }
+                "--record" => parsed.record = Some(value()?.into()),
+                "--play" => parsed.play = Some(value()?.into()),
+                "--export" => parsed.export = Some(value()?.into()),
+                "--fps" => {
+                    let v = value()?;
+                    parsed.fps = match v.parse() {
+                        Ok(fps) if fps > 0.0 => fps,
+                        _ => return Err(format!("Invalid fps {:?}", v)),
+                    };
+                }
+                "-h" | "--help" => return Err(USAGE.to_owned()),
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+                _ => model = Some(PathBuf::from(arg)),
+            }
+        }
+
+        parsed.model = model.ok_or_else(|| USAGE.to_owned())?;
+        if parsed.export.is_some() && parsed.play.is_none() {
+            return Err("--export requires a path to --play".to_owned());
+        }
+        Ok(parsed)
+    }
+}
+
+impl App for Recorder {
+    fn update(&mut self, _engine: &mut Engine, dt: f32) {
+        self.time += dt;
+    }
+
+    fn on_event(&mut self, engine: &mut Engine, event: &Event<()>) {
+        // Check once per frame, after all of its input arrived
+        if let Event::MainEventsCleared = event {
+            if !engine.action_state().pressed("record_keyframe") {
+                return;
+            }
+            match self.path.record(self.time, &engine.camera) {
+                Ok(()) => log::info!(
+                    "Recorded keyframe {} at {:.2}s",
+                    self.path.keyframes.len(),
+                    self.time
+                ),
+                Err(e) => log::error!("{}", e),
+            }
+        }
+    }
+
+    fn on_exit(&mut self, _engine: &mut Engine) {
+        match self.path.save(&self.file) {
+            Ok(()) => log::info!("Saved camera path {}", self.file.display()),
+            Err(e) => log::error!("{}", e),
+        }
+    }
+}
+
+fn parse_vec3(v: &str) -> Result<Vec3, String> {
+    let c = v
+        .split(',')
+        .map(|x| x.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("Invalid vector {:?}", v))?;
+    match c[..] {
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => Err(format!("Invalid vector {:?}", v)),
+    }
 }
 
-fn main() {
+/// World space bounds of all the objects, none if they have no vertices
+fn bounds(scene: &Scene) -> Option<(Vec3, Vec3)> {
+    scene
+        .objects
+        .values()
+        .flat_map(|o| {
+            o.meshes
+                .iter()
+                .flat_map(|m| &m.vertices)
+                .map(move |v| o.world.transform_point3(v.position))
+        })
+        .fold(None, |a, p| match a {
+            Some((min, max)) => Some((p.min(min), p.max(max))),
+            None => Some((p, p)),
+        })
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
     // Initialize logging
     let log_env = env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info");
     env_logger::init_from_env(log_env);
-    log::info!("Hello there!");
 
-    // Prepare the engine params
-    let params = EngineParams {
-        window: WindowParams { size: (1280, 720) },
+    // Parse arguments
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
     };
 
+    // Prepare the engine params, config file first and environment on top
+    let mut params = EngineParams::default().with_title("natsukashii");
+    params
+        .actions
+        .bind("record_keyframe", Binding::Key(VirtualKeyCode::K));
+    if let Some(size) = args.size {
+        params = params.with_size(size);
+    }
+    if let Some(config) = &args.config {
+        params = params.with_config(&EngineConfig::from_file(config)?)?;
+    }
+    params = params.with_config(&EngineConfig::from_env()?)?;
+
     // Create the engine
-    let mut engine = futures::executor::block_on(Engine::new(&params));
-
-    // Create cornell box
-    let model = Model::cornell_box();
-    let meshes = model.meshes.centered();
-    let materials = model
-        .mesh_materials
-        .iter()
-        .map(|m| {
-            m.as_ref()
-                .map(|m| {
-                    model
-                        .materials
-                        .iter()
-                        .filter(|x| *m == x.name)
-                        .next()
-                        .map(|m| (m.albedo,))
-                })
-                .flatten()
-        })
-        .collect();
-
-    // Create demo scene
-    let cpos = (0.0, 0.0, -3.5).into();
-    let view = Mat4::look_at_lh(cpos, Vec3::ZERO, Vec3::Y);
-    let scene = Scene {
-        objects: vec![SceneObject {
-            meshes,
-            materials,
-            transform: Mat4::IDENTITY,
-        }],
-        view,
+    let headless = args.screenshot.is_some() || args.export.is_some();
+    let mut engine = match headless {
+        true => futures::executor::block_on(Engine::new_headless(&params))?,
+        false => futures::executor::block_on(Engine::new(&params))?,
+    };
+
+    // Load the scene, or a scene holding just the model
+    log::info!("Loading {}", args.model.display());
+    let mut scene = match args.model.extension().and_then(OsStr::to_str) {
+        Some("ron") => Scene::load(&args.model)?,
+        _ => {
+            let model = Model::from_path(&args.model, &args.import)?;
+            let mut scene = Scene::default();
+            scene.add_model(&model, Mat4::IDENTITY);
+            scene
+        }
     };
+
+    // Frame the scene unless told otherwise, empty ones around the origin
+    let (center, radius) = match bounds(&scene) {
+        Some((min, max)) => ((min + max) / 2.0, (max - min).length() / 2.0),
+        None => (Vec3::ZERO, 1.0),
+    };
+    let saved = scene.camera;
+    let target = args
+        .target
+        .or_else(|| saved.map(|c| c.target))
+        .unwrap_or(center);
+    let cpos = args
+        .camera
+        .or_else(|| saved.map(|c| c.position))
+        .unwrap_or_else(|| center - Vec3::Z * radius.max(0.1) * 2.5);
+    if cpos == target {
+        return Err("Camera position and target must differ".into());
+    }
+
+    // Light the scene from the camera if it has no lights of its own
+    if scene.lights.is_empty() {
+        scene.lights.push(Light::directional(target - cpos));
+    }
+
+    // Create the scene
+    scene.view = Mat4::look_at_lh(cpos, target, Vec3::Y);
+    scene.camera = Some(SceneCamera {
+        position: cpos,
+        target,
+        projection: saved.and_then(|c| c.projection),
+    });
     engine.set_scene(scene);
-    engine.set_camera_position(cpos);
 
-    // Run
-    engine.run();
+    // Render the camera path frame by frame if asked to
+    let path = args.play.as_deref().map(CameraPath::load).transpose()?;
+    if let (Some(path), Some(dir)) = (&path, &args.export) {
+        fs::create_dir_all(dir)?;
+        engine.export_path(path, args.fps, dir)?;
+        return Ok(());
+    }
+
+    // Pick the camera controller, orbiting ones circle around the target
+    let controller: Box<dyn CameraController> = match (path, args.controller.as_str()) {
+        (Some(path), _) => {
+            let mut controller = PathController::new(path);
+            controller.looping = true;
+            Box::new(controller)
+        }
+        (None, "orbit") => Box::new(OrbitController::new(target)),
+        (None, "turntable") => Box::new(TurntableController::new(target)),
+        _ => Box::new(FlyController),
+    };
+    engine.set_controller(controller);
+
+    match (&args.screenshot, args.record) {
+        (Some(path), _) => {
+            // Render the requested frames and save the last one
+            for _ in 1..args.frames.max(1) {
+                engine.update_fixed(&mut ());
+                engine.render()?;
+            }
+            engine.update_fixed(&mut ());
+            engine.save_screenshot(path)?;
+        }
+        (None, Some(file)) => engine.run(Recorder {
+            file,
+            path: CameraPath::default(),
+            time: 0.0,
+        }),
+        (None, None) => engine.run(()),
+    }
+
+    Ok(())
 }
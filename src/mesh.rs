@@ -2,10 +2,11 @@
 // mesh.rs
 //
 
-use super::geometry::Positions;
+use super::geometry::{Bounds, Positions};
 use bytemuck::{Pod, Zeroable};
-use glam::Vec3;
-use std::mem::size_of;
+use glam::{Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, mem::size_of};
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 
 #[derive(Clone, Debug)]
@@ -18,6 +19,8 @@ pub struct MeshBuffers {
     pub vbuf: wgpu::Buffer,
     pub ibuf: wgpu::Buffer,
     pub nelems: u32,
+    /// Bounds of the vertices in model space
+    pub bbox: (Vec3, Vec3),
 }
 
 #[repr(C)]
@@ -27,10 +30,31 @@ pub struct Vertex {
     _pad0: f32,
     pub normal: Vec3,
     _pad1: f32,
+    pub uv: Vec2,
 }
 
 pub type Index = u32;
 
+/// How `Mesh::generate_normals_with` computes normals
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NormalOptions {
+    pub mode: NormalMode,
+    /// Largest angle in radians between faces smoothed together,
+    /// edges between faces meeting at wider angles are kept hard
+    pub crease_angle: Option<f32>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NormalMode {
+    /// Normal of every face, splitting all shared vertices
+    Flat,
+    /// Average of the faces around a vertex weighted by their area
+    Area,
+    /// Average of the faces around a vertex weighted by their angle at it
+    Angle,
+}
+
 #[allow(dead_code)]
 impl Mesh {
     pub fn create_buffers(&self, device: &wgpu::Device) -> MeshBuffers {
@@ -45,7 +69,12 @@ impl Mesh {
             usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_SRC,
         });
         let nelems = self.indices.len() as _;
-        MeshBuffers { vbuf, ibuf, nelems }
+        MeshBuffers {
+            vbuf,
+            ibuf,
+            nelems,
+            bbox: self.bbox(),
+        }
     }
 
     pub fn vertex(&self, face: usize, vert: usize) -> Vertex {
@@ -53,27 +82,97 @@ impl Mesh {
         self.vertices[idx + vert]
     }
 
+    /// Generates area weighted smooth normals
     pub fn generate_normals(&mut self) {
-        let verts = &mut self.vertices;
-
-        for i in self.indices.chunks_exact(3) {
-            let v1 = verts[i[0] as usize];
-            let v2 = verts[i[1] as usize];
-            let v3 = verts[i[2] as usize];
+        self.generate_normals_with(NormalOptions::default());
+    }
 
-            let e1 = v2.position - v1.position;
-            let e2 = v3.position - v1.position;
+    /// Generates normals, splitting vertices whose faces need different ones.
+    ///
+    /// Faces sharing a position are smoothed together even across texture seams
+    pub fn generate_normals_with(&mut self, options: NormalOptions) {
+        // Compute face normals and the weighted normal at every face corner
+        let faces: Vec<[usize; 3]> = self
+            .indices
+            .chunks_exact(3)
+            .map(|i| [i[0] as usize, i[1] as usize, i[2] as usize])
+            .collect();
+        let mut face_normals = Vec::with_capacity(faces.len());
+        let mut weighted = Vec::with_capacity(faces.len() * 3);
+        for f in &faces {
+            let p = f.map(|i| self.vertices[i].position);
+            let n = (p[1] - p[0]).cross(p[2] - p[0]);
+            let unit = n.normalize_or_zero();
+            face_normals.push(unit);
+            for k in 0..3 {
+                weighted.push(match options.mode {
+                    NormalMode::Angle => {
+                        let e1 = (p[(k + 1) % 3] - p[k]).normalize_or_zero();
+                        let e2 = (p[(k + 2) % 3] - p[k]).normalize_or_zero();
+                        unit * e1.dot(e2).clamp(-1.0, 1.0).acos()
+                    }
+                    // Cross product length is twice the area
+                    _ => n,
+                });
+            }
+        }
 
-            let n = e1.cross(e2);
+        // Group the corners at the same position, folding negative zeros
+        let mut groups: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+        for (c, &v) in faces.iter().flatten().enumerate() {
+            let key = self.vertices[v]
+                .position
+                .to_array()
+                .map(|x| (x + 0.0).to_bits());
+            groups.entry(key).or_default().push(c);
+        }
 
-            verts[i[0] as usize].normal += n;
-            verts[i[1] as usize].normal += n;
-            verts[i[2] as usize].normal += n;
+        // Sum the corners of the group whose faces are within the crease angle
+        let min_cos = options.crease_angle.map_or(f32::MIN, f32::cos);
+        let mut normals = vec![Vec3::ZERO; weighted.len()];
+        for corners in groups.values() {
+            for &c in corners {
+                let n = face_normals[c / 3];
+                normals[c] = match options.mode {
+                    NormalMode::Flat => n,
+                    _ => corners
+                        .iter()
+                        .filter(|&&o| n.dot(face_normals[o / 3]) >= min_cos)
+                        .fold(Vec3::ZERO, |sum, &o| sum + weighted[o])
+                        .normalize_or_zero(),
+                };
+            }
         }
 
-        for v in &mut self.vertices {
-            let n = v.normal.normalize();
-            v.normal = n
+        // Split vertices used with different normals
+        let mut vertices = Vec::with_capacity(self.vertices.len());
+        let mut remap: HashMap<(usize, [u32; 3]), Index> = HashMap::new();
+        let indices = faces
+            .iter()
+            .flatten()
+            .zip(&normals)
+            .map(|(&v, &normal)| {
+                let key = (v, normal.to_array().map(f32::to_bits));
+                *remap.entry(key).or_insert_with(|| {
+                    vertices.push(Vertex {
+                        normal,
+                        ..self.vertices[v]
+                    });
+                    (vertices.len() - 1) as Index
+                })
+            })
+            .collect();
+
+        self.vertices = vertices;
+        self.indices = indices;
+    }
+}
+
+impl Default for NormalOptions {
+    fn default() -> Self {
+        NormalOptions {
+            mode: NormalMode::Area,
+            crease_angle: None,
         }
     }
 }
@@ -102,6 +201,11 @@ impl Vertex {
             shader_location: 1,
             format: wgpu::VertexFormat::Float32x3,
         },
+        wgpu::VertexAttribute {
+            offset: size_of::<[f32; 8]>() as _,
+            shader_location: 2,
+            format: wgpu::VertexFormat::Float32x2,
+        },
     ];
 
     pub fn new(position: Vec3) -> Self {
@@ -137,3 +241,74 @@ impl IndexFormat for u32 {
         wgpu::IndexFormat::Uint32
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::assert_close;
+
+    /// Two right triangles folded at 90 degrees along their shared edge from the origin
+    /// to +Z, facing +Y and +X. The one facing +X has three times the area
+    fn fold() -> Mesh {
+        let positions = [Vec3::ZERO, Vec3::Z, Vec3::X, Vec3::new(0.0, 3.0, 0.0)];
+        Mesh {
+            vertices: positions.iter().map(|&p| Vertex::new(p)).collect(),
+            indices: vec![0, 1, 2, 0, 3, 1],
+        }
+    }
+
+    fn generate(mode: NormalMode, crease_angle: Option<f32>) -> Mesh {
+        let mut mesh = fold();
+        mesh.generate_normals_with(NormalOptions { mode, crease_angle });
+        mesh
+    }
+
+    /// Normals of the vertices at `position`
+    fn normals_at(mesh: &Mesh, position: Vec3) -> Vec<Vec3> {
+        let vertices = mesh.vertices.iter().filter(|v| v.position == position);
+        vertices.map(|v| v.normal).collect()
+    }
+
+    #[test]
+    fn flat_normals_split_shared_vertices() {
+        let mesh = generate(NormalMode::Flat, None);
+        assert_eq!(mesh.vertices.len(), 6);
+        for face in mesh.indices.chunks(3) {
+            let normals: Vec<_> = face
+                .iter()
+                .map(|&i| mesh.vertices[i as usize].normal)
+                .collect();
+            assert!(normals == [Vec3::Y; 3] || normals == [Vec3::X; 3]);
+        }
+    }
+
+    #[test]
+    fn smooth_normals_are_weighted() {
+        let area = generate(NormalMode::Area, None);
+        assert_eq!(area.vertices.len(), 4);
+        assert_close(
+            normals_at(&area, Vec3::ZERO)[0],
+            Vec3::new(3.0, 1.0, 0.0).normalize(),
+        );
+
+        // Both faces have a right angle at the origin
+        let angle = generate(NormalMode::Angle, None);
+        assert_eq!(angle.vertices.len(), 4);
+        assert_close(
+            normals_at(&angle, Vec3::ZERO)[0],
+            Vec3::new(1.0, 1.0, 0.0).normalize(),
+        );
+    }
+
+    #[test]
+    fn crease_angle_keeps_sharp_edges() {
+        let sharp = generate(NormalMode::Area, Some(45f32.to_radians()));
+        assert_eq!(sharp.vertices.len(), 6);
+        let mut normals = normals_at(&sharp, Vec3::Z);
+        normals.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+        assert_eq!(normals, [Vec3::Y, Vec3::X]);
+
+        let smooth = generate(NormalMode::Area, Some(100f32.to_radians()));
+        assert_eq!(smooth.vertices.len(), 4);
+    }
+}
@@ -2,9 +2,21 @@
 // model.rs
 //
 
-use super::mesh::{Mesh, Vertex};
-use glam::Vec3;
-use std::{collections::HashMap, ffi::OsStr, io::BufRead, path::Path};
+use super::mesh::{Mesh, NormalMode, NormalOptions, Vertex};
+use glam::{Mat4, Vec2, Vec3};
+use image::{imageops, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    ffi::OsStr,
+    fmt, fs,
+    io::{self, BufRead},
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 use tobj::{load_mtl_buf, load_obj_buf, LoadOptions};
 
 macro_rules! model_file {
@@ -26,42 +38,236 @@ macro_rules! model_buffers {
 #[derive(Default, Debug)]
 pub struct Model {
     pub name: String,
+    /// File the model was loaded from, if any
+    pub path: Option<PathBuf>,
+    /// Settings the model was imported with
+    pub options: ImportOptions,
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
     pub mesh_materials: Vec<Option<String>>,
+    pub nodes: Vec<ModelNode>,
 }
 
-#[derive(Debug)]
+/// Settings applied while importing a Model
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImportOptions {
+    /// How normals are generated for meshes without authored ones.
+    /// OBJ smoothing groups (`s` statements) are not read, a crease angle
+    /// keeps the hard edges they would otherwise mark
+    pub normals: NormalOptions,
+    /// Generates normals even for meshes with authored ones
+    pub regenerate_normals: bool,
+}
+
+/// Placement of meshes within a Model
+#[derive(Clone, Debug)]
+pub struct ModelNode {
+    pub name: String,
+    /// Index into `Model::nodes`, always preceding this node.
+    /// Root nodes are placed relative to the model origin
+    pub parent: Option<usize>,
+    /// Transform relative to the parent
+    pub transform: Mat4,
+    /// Indices into `Model::meshes`
+    pub meshes: Vec<usize>,
+}
+
+/// Surface material, with factors scaling their maps.
+///
+/// Metallic-roughness materials use `metallic` and `roughness`,
+/// while Blinn-Phong ones use `ambient`, `specular` and `shininess`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Material {
     pub name: String,
-    pub albedo: Vec3,
+    pub shading: Shading,
+    pub base_color: Vec3,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub ambient: Vec3,
+    pub specular: Vec3,
+    pub shininess: f32,
+    pub emissive: Vec3,
+    /// Opacity, translucent surfaces are blended over opaque ones
+    pub dissolve: f32,
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    /// MTL `illum` statement
+    pub illumination_model: Option<u8>,
+    /// MTL statements the loader does not recognize, including PBR extensions
+    pub params: BTreeMap<String, String>,
+    /// Not stored in scene files, restored from the model instead
+    #[serde(skip)]
+    pub maps: MaterialMaps,
+}
+
+/// Lighting model of a Material
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Shading {
+    /// Cook-Torrance GGX driven by metallic and roughness
+    MetallicRoughness,
+    /// Normalized Blinn-Phong driven by specular and shininess
+    BlinnPhong,
+}
+
+/// Texture maps of a Material, all sampled with the first texture coordinates
+#[derive(Clone, Debug, Default)]
+pub struct MaterialMaps {
+    pub base_color: Option<Arc<Texture>>,
+    /// Roughness in green and metallic in blue
+    pub metallic_roughness: Option<Arc<Texture>>,
+    /// Tangent space normal, green pointing up the image
+    pub normal: Option<Arc<Texture>>,
+    /// Ambient occlusion in red
+    pub occlusion: Option<Arc<Texture>>,
+    pub emissive: Option<Arc<Texture>>,
+}
+
+/// Decoded texture image
+#[derive(Clone)]
+pub struct Texture {
+    pub name: String,
+    pub image: RgbaImage,
+}
+
+/// Errors reported while loading a Model
+#[derive(Debug)]
+pub enum ModelError {
+    /// A referenced file does not exist
+    MissingFile(PathBuf),
+    /// A referenced file exists but could not be read
+    Io { path: PathBuf, error: io::Error },
+    /// The OBJ file could not be parsed
+    Obj {
+        path: PathBuf,
+        error: tobj::LoadError,
+    },
+    /// A material library could not be parsed
+    Mtl {
+        path: PathBuf,
+        error: tobj::LoadError,
+    },
+    /// A texture could not be decoded
+    Image {
+        path: PathBuf,
+        error: image::ImageError,
+    },
+    /// No OBJ file was found among the given buffers
+    NoObj,
+    /// The glTF file could not be imported
+    Gltf { path: PathBuf, error: gltf::Error },
 }
 
 impl Model {
-    pub fn from_buffers<R: BufRead + Copy>(name: &str, buffers: HashMap<String, R>) -> Self {
+    /// Loads a model file, picking the importer by its extension.
+    ///
+    /// Supports `.gltf` and `.glb` through `Model::from_gltf`,
+    /// and treats anything else as OBJ
+    pub fn from_path(path: &Path, options: &ImportOptions) -> Result<Self, ModelError> {
+        match path.extension().and_then(OsStr::to_str) {
+            Some("gltf") | Some("glb") => Self::from_gltf(path, options),
+            _ => Self::from_obj(path, options),
+        }
+    }
+
+    /// Loads an OBJ file, resolving its material libraries
+    /// and textures relative to the directory of the OBJ
+    pub fn from_obj(path: &Path, options: &ImportOptions) -> Result<Self, ModelError> {
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let file = path.file_name().and_then(OsStr::to_str).unwrap_or_default();
+        let name = path.file_stem().and_then(OsStr::to_str).unwrap_or_default();
+
+        let mut model = Self::load(name, dir, file, options, |path| {
+            fs::read(path).map_err(|error| match error.kind() {
+                io::ErrorKind::NotFound => ModelError::MissingFile(path.into()),
+                _ => ModelError::Io {
+                    path: path.into(),
+                    error,
+                },
+            })
+        })?;
+        model.path = Some(path.into());
+        Ok(model)
+    }
+
+    /// Loads an OBJ file from in memory buffers, keyed by their file names
+    pub fn from_buffers<R: BufRead + Copy>(
+        name: &str,
+        buffers: HashMap<String, R>,
+        options: &ImportOptions,
+    ) -> Result<Self, ModelError> {
         let file = buffers
             .keys()
-            .filter(|f| extension_from_filename(f) == "obj")
-            .next()
-            .unwrap();
+            .find(|f| extension_from_filename(f) == "obj")
+            .ok_or(ModelError::NoObj)?;
 
-        let mut buf = buffers[file];
+        // Buffers have no directory, their paths are just the file names
+        Self::load(name, Path::new(""), file, options, |path| {
+            let mut buf = *path
+                .to_str()
+                .and_then(|f| buffers.get(f))
+                .ok_or_else(|| ModelError::MissingFile(path.into()))?;
+            let mut data = vec![];
+            buf.read_to_end(&mut data).map_err(|error| ModelError::Io {
+                path: path.into(),
+                error,
+            })?;
+            Ok(data)
+        })
+    }
+
+    /// Loads the OBJ `file`, reading it and the files it references
+    /// through `read` with their paths joined onto `dir`
+    fn load<F>(
+        name: &str,
+        dir: &Path,
+        file: &str,
+        options: &ImportOptions,
+        read: F,
+    ) -> Result<Self, ModelError>
+    where
+        F: Fn(&Path) -> Result<Vec<u8>, ModelError>,
+    {
+        // Keep the first material library error along with its path,
+        // tobj only gets to see its kind
+        let mtl_error = RefCell::new(None);
+
+        let obj_path = dir.join(file);
+        let obj = read(&obj_path)?;
         let (mdls, mats) = load_obj_buf(
-            &mut buf,
+            &mut &obj[..],
             &LoadOptions {
                 triangulate: true,
                 single_index: true,
                 ..Default::default()
             },
             |p| {
-                let mut buf = buffers[p.to_str().unwrap()];
-                load_mtl_buf(&mut buf)
+                let path = dir.join(p);
+                let parsed = read(&path).and_then(|mtl| {
+                    load_mtl_buf(&mut &mtl[..]).map_err(|error| ModelError::Mtl { path, error })
+                });
+                parsed.map_err(|e| {
+                    let kind = match &e {
+                        ModelError::Mtl { error, .. } => *error,
+                        _ => tobj::LoadError::OpenFileFailed,
+                    };
+                    mtl_error.borrow_mut().get_or_insert(e);
+                    kind
+                })
             },
         )
-        .unwrap();
+        .map_err(|error| ModelError::Obj {
+            path: obj_path,
+            error,
+        })?;
+        if let Some(e) = mtl_error.into_inner() {
+            return Err(e);
+        }
+        // Every material library error was caught above
         let mats = mats.unwrap_or_default();
 
-        let meshes = mdls
+        let meshes: Vec<Mesh> = mdls
             .iter()
             .map(|m| {
                 let mesh = &m.mesh;
@@ -71,27 +277,106 @@ impl Model {
                     .map(|i| [i[2], i[1], i[0]])
                     .flatten()
                     .collect();
+                let invert_z = Vec3::new(1.0, 1.0, -1.0);
+                let has_normals = mesh.normals.len() == mesh.positions.len();
                 let vertices = (0..(mesh.positions.len() / 3))
                     .map(|i| {
                         let pos = &mesh.positions[(i * 3)..(i * 3 + 3)];
-                        let invert_z = Vec3::new(1.0, 1.0, -1.0);
-                        Vertex::new(Vec3::from_slice(pos) * invert_z)
+                        let mut v = Vertex::new(Vec3::from_slice(pos) * invert_z);
+                        if has_normals {
+                            let normal = &mesh.normals[(i * 3)..(i * 3 + 3)];
+                            v.normal = Vec3::from_slice(normal) * invert_z;
+                        }
+
+                        // OBJ places the texture origin at the bottom left
+                        if let Some(uv) = mesh.texcoords.get((i * 2)..(i * 2 + 2)) {
+                            v.uv = Vec2::new(uv[0], 1.0 - uv[1]);
+                        }
+                        v
                     })
                     .collect();
 
                 let mut mesh = Mesh { vertices, indices };
-                mesh.generate_normals();
+                if !has_normals || options.regenerate_normals {
+                    mesh.generate_normals_with(options.normals);
+                }
                 mesh
             })
             .collect();
 
+        // Decode every texture once, materials may share them
+        let mut textures: HashMap<String, Arc<Texture>> = HashMap::new();
+        let mut texture = |t: &str| -> Result<Arc<Texture>, ModelError> {
+            if let Some(texture) = textures.get(t) {
+                return Ok(texture.clone());
+            }
+            let path = dir.join(t);
+            let texture = Texture::decode(t, &read(&path)?)
+                .map_err(|error| ModelError::Image { path, error })?;
+            let texture = Arc::new(texture);
+            textures.insert(t.to_owned(), texture.clone());
+            Ok(texture)
+        };
         let materials = mats
             .iter()
-            .map(|m| Material {
-                name: m.name.clone(),
-                albedo: Vec3::from_slice(&m.diffuse),
+            .map(|m| {
+                // Prefer the PBR extension statements, falling back to classic ones
+                let param = |key: &str| m.unknown_param.get(key).map(String::as_str);
+                let factor = |key: &str| param(key).and_then(|v| parse_floats::<1>(key, v));
+                let mut map = |file: Option<&str>| match file.and_then(texture_file) {
+                    Some(t) => texture(t).map(Some),
+                    None => Ok(None),
+                };
+
+                // Classic materials keep their Blinn-Phong look
+                let pbr = ["Pr", "Pm", "map_Pr", "map_Pm"]
+                    .iter()
+                    .any(|key| param(key).is_some());
+                let shading = match pbr {
+                    true => Shading::MetallicRoughness,
+                    false => Shading::BlinnPhong,
+                };
+
+                let metallic_map = map(param("map_Pm"))?;
+                let roughness_map = map(param("map_Pr"))?;
+                let metallic_roughness = match (metallic_map, roughness_map) {
+                    (None, None) => None,
+                    (m, r) => Some(Arc::new(Texture::pack_metallic_roughness(
+                        m.as_deref(),
+                        r.as_deref(),
+                    ))),
+                };
+                let maps = MaterialMaps {
+                    base_color: map(Some(m.diffuse_texture.as_str()))?,
+                    metallic_roughness,
+                    normal: map(param("norm").or(Some(m.normal_texture.as_str())))?,
+                    occlusion: None,
+                    emissive: map(param("map_Ke"))?,
+                };
+
+                Ok(Material {
+                    name: m.name.clone(),
+                    shading,
+                    base_color: Vec3::from_slice(&m.diffuse),
+                    metallic: factor("Pm").map_or(0.0, |[v]| v),
+                    // Approximate the roughness of Blinn-Phong shininess
+                    roughness: factor("Pr")
+                        .map_or_else(|| (2.0 / (m.shininess + 2.0)).sqrt(), |[v]| v),
+                    ambient: Vec3::from_slice(&m.ambient),
+                    specular: Vec3::from_slice(&m.specular),
+                    shininess: m.shininess,
+                    emissive: param("Ke")
+                        .and_then(|v| parse_floats::<3>("Ke", v))
+                        .map_or(Vec3::ZERO, Vec3::from),
+                    // tobj only reads `d`, and leaves it at 1 when a file uses `Tr` instead
+                    dissolve: factor("Tr").map_or(m.dissolve, |[t]| m.dissolve.min(1.0 - t)),
+                    illumination_model: m.illumination_model,
+                    params: m.unknown_param.clone().into_iter().collect(),
+                    maps,
+                    ..Default::default()
+                })
             })
-            .collect();
+            .collect::<Result<_, ModelError>>()?;
 
         let mesh_materials = mdls
             .iter()
@@ -101,23 +386,298 @@ impl Model {
             })
             .collect();
 
-        Model {
+        // OBJ has no hierarchy, place everything at the origin
+        let nodes = vec![ModelNode {
+            name: name.to_owned(),
+            parent: None,
+            transform: Mat4::IDENTITY,
+            meshes: (0..meshes.len()).collect(),
+        }];
+
+        Ok(Model {
             name: name.to_owned(),
+            path: None,
+            options: *options,
             meshes,
             materials,
             mesh_materials,
-        }
+            nodes,
+        })
+    }
+
+    /// Material of the `index`th mesh, if it has one
+    pub fn mesh_material(&self, index: usize) -> Option<&Material> {
+        self.mesh_materials[index]
+            .as_ref()
+            .and_then(|m| self.materials.iter().find(|x| *m == x.name))
     }
 
     pub fn cornell_box() -> Self {
         let buffers = model_buffers!("cornell_box", "cornell_box.obj", "cornell_box.mtl");
-        Self::from_buffers("cornell_box", buffers)
+        // The boxes have no normals, keep their edges sharp
+        let options = ImportOptions {
+            normals: NormalOptions {
+                mode: NormalMode::Flat,
+                crease_angle: None,
+            },
+            ..Default::default()
+        };
+        Self::from_buffers("cornell_box", buffers, &options).expect("Embedded model is valid")
     }
 }
 
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            name: String::new(),
+            shading: Shading::MetallicRoughness,
+            base_color: Vec3::ONE,
+            metallic: 0.0,
+            roughness: 0.5,
+            ambient: Vec3::ZERO,
+            specular: Vec3::ZERO,
+            shininess: 0.0,
+            emissive: Vec3::ZERO,
+            dissolve: 1.0,
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            illumination_model: None,
+            params: BTreeMap::new(),
+            maps: MaterialMaps::default(),
+        }
+    }
+}
+
+impl Texture {
+    /// Decodes an encoded image file of any supported format
+    pub fn decode(name: &str, data: &[u8]) -> Result<Self, image::ImageError> {
+        let image = image::load_from_memory(data)?.to_rgba8();
+        Ok(Texture {
+            name: name.to_owned(),
+            image,
+        })
+    }
+
+    /// Single white texel, bound in place of missing textures
+    pub fn white() -> Self {
+        Texture {
+            name: "white".to_owned(),
+            image: RgbaImage::from_pixel(1, 1, image::Rgba([255; 4])),
+        }
+    }
+
+    /// Packs separate single channel maps the way `MaterialMaps::metallic_roughness` expects,
+    /// at the size of the larger one. A missing map leaves its factor unscaled
+    pub fn pack_metallic_roughness(
+        metallic: Option<&Texture>,
+        roughness: Option<&Texture>,
+    ) -> Self {
+        let maps = [metallic, roughness];
+        let (width, height) = maps
+            .iter()
+            .flatten()
+            .map(|t| t.image.dimensions())
+            .fold((1, 1), |a, d| (a.0.max(d.0), a.1.max(d.1)));
+        let [metallic, roughness] = maps.map(|t| {
+            t.map(|t| match t.image.dimensions() == (width, height) {
+                true => t.image.clone(),
+                false => imageops::resize(&t.image, width, height, imageops::FilterType::Triangle),
+            })
+        });
+
+        let image = RgbaImage::from_fn(width, height, |x, y| {
+            let channel =
+                |map: &Option<RgbaImage>| map.as_ref().map_or(255, |m| m.get_pixel(x, y)[0]);
+            image::Rgba([0, channel(&roughness), channel(&metallic), 255])
+        });
+        let names: Vec<&str> = maps.iter().flatten().map(|t| t.name.as_str()).collect();
+        Texture {
+            name: names.join("+"),
+            image,
+        }
+    }
+
+    /// Uploads the image along with a full mip chain.
+    /// Color maps are `srgb` encoded, while data maps are linear
+    pub fn create_view(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        srgb: bool,
+    ) -> wgpu::TextureView {
+        let (width, height) = self.image.dimensions();
+        let mip_level_count = 32 - width.max(height).max(1).leading_zeros();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: match srgb {
+                true => wgpu::TextureFormat::Rgba8UnormSrgb,
+                false => wgpu::TextureFormat::Rgba8Unorm,
+            },
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            label: Some(&self.name),
+        });
+
+        // Halve every level from the previous one
+        let mut level = self.image.clone();
+        for mip_level in 0..mip_level_count {
+            if mip_level > 0 {
+                let (w, h) = level.dimensions();
+                let filter = imageops::FilterType::Triangle;
+                level = imageops::resize(&level, (w / 2).max(1), (h / 2).max(1), filter);
+            }
+            let (w, h) = level.dimensions();
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &level,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(4 * w),
+                    rows_per_image: None,
+                },
+                wgpu::Extent3d {
+                    width: w,
+                    height: h,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        texture.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+}
+
+impl fmt::Debug for Texture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Texture")
+            .field("name", &self.name)
+            .field("size", &self.image.dimensions())
+            .finish()
+    }
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelError::MissingFile(p) => write!(f, "Missing file {}", p.display()),
+            ModelError::Io { path, error } => {
+                write!(f, "Failed to read {}: {}", path.display(), error)
+            }
+            ModelError::Obj { path, error } => {
+                write!(f, "Failed to parse OBJ {}: {}", path.display(), error)
+            }
+            ModelError::Mtl { path, error } => {
+                write!(f, "Failed to parse MTL {}: {}", path.display(), error)
+            }
+            ModelError::Image { path, error } => {
+                write!(f, "Failed to decode texture {}: {}", path.display(), error)
+            }
+            ModelError::NoObj => write!(f, "No OBJ file given"),
+            ModelError::Gltf { path, error } => {
+                write!(f, "Failed to import glTF {}: {}", path.display(), error)
+            }
+        }
+    }
+}
+
+impl Error for ModelError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ModelError::Io { error, .. } => Some(error),
+            ModelError::Obj { error, .. } => Some(error),
+            ModelError::Mtl { error, .. } => Some(error),
+            ModelError::Image { error, .. } => Some(error),
+            ModelError::Gltf { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// File name of a texture statement, skipping its options.
+/// The name is the rest of the statement, so it may contain spaces
+fn texture_file(statement: &str) -> Option<&str> {
+    let mut rest = statement.trim();
+    while rest.starts_with('-') {
+        let (option, args) = split_word(rest);
+        // Offsets, scales and turbulence take one to three numbers
+        let (min, max) = match option {
+            "-mm" => (2, 2),
+            "-o" | "-s" | "-t" => (1, 3),
+            _ => (1, 1),
+        };
+        rest = args;
+        for i in 0..max {
+            let (arg, after) = split_word(rest);
+            if i >= min && arg.parse::<f32>().is_err() {
+                break;
+            }
+            rest = after;
+        }
+    }
+    Some(rest).filter(|f| !f.is_empty())
+}
+
+/// Splits the first word off `s`, trimming the whitespace around it
+fn split_word(s: &str) -> (&str, &str) {
+    let s = s.trim_start();
+    match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], s[i..].trim_start()),
+        None => (s, ""),
+    }
+}
+
+/// Parses the `N` values of an MTL statement, warning about invalid ones
+fn parse_floats<const N: usize>(key: &str, value: &str) -> Option<[f32; N]> {
+    let mut values = [0.0; N];
+    let mut words = value.split_whitespace();
+    for v in &mut values {
+        match words.next().map(str::parse) {
+            Some(Ok(x)) => *v = x,
+            _ => {
+                log::warn!("Ignoring invalid MTL statement {} {}", key, value);
+                return None;
+            }
+        }
+    }
+    Some(values)
+}
+
 fn extension_from_filename(filename: &str) -> &str {
     Path::new(filename)
         .extension()
         .and_then(OsStr::to_str)
         .unwrap_or("")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn texture_files_skip_options() {
+        assert_eq!(texture_file("tex.png"), Some("tex.png"));
+        assert_eq!(texture_file("  my tex.png "), Some("my tex.png"));
+        assert_eq!(
+            texture_file("-bm 0.5 normal map.png"),
+            Some("normal map.png")
+        );
+        assert_eq!(
+            texture_file("-s 2 2 -o 0.5 -clamp on -mm 0 1 tex.png"),
+            Some("tex.png")
+        );
+        assert_eq!(texture_file("-s 2 2 1 3.png"), Some("3.png"));
+        assert_eq!(texture_file("-clamp on"), None);
+        assert_eq!(texture_file(""), None);
+    }
+}
@@ -0,0 +1,128 @@
+//
+// offscreen.rs
+//
+
+use super::error::EngineError;
+use image::RgbaImage;
+use std::num::NonZeroU32;
+
+/// Offscreen render target
+///
+/// Stands in for the window surface when rendering headless,
+/// and allows reading back the rendered frame to the CPU
+pub struct Offscreen {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub size: (u32, u32),
+    pub format: wgpu::TextureFormat,
+}
+
+impl Offscreen {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+    pub fn new(device: &wgpu::Device, surface_conf: &wgpu::SurfaceConfiguration) -> Self {
+        let size = (surface_conf.width, surface_conf.height);
+        let format = surface_conf.format;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            label: None,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            size,
+            format,
+        }
+    }
+
+    /// Copies the contents of the target back to the CPU as an RGBA image.
+    ///
+    /// Only 8 bit RGBA and BGRA targets can be read back,
+    /// for any other format `EngineError::UnsupportedFormat` is returned
+    pub fn read(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<RgbaImage, EngineError> {
+        let (width, height) = self.size;
+        let bgra = match self.format {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            format => return Err(EngineError::UnsupportedFormat(format)),
+        };
+
+        // Rows of the staging buffer must be aligned to COPY_BYTES_PER_ROW_ALIGNMENT
+        let bytes_per_pixel = 4;
+        let unpadded_bytes_per_row = width * bytes_per_pixel;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+
+        // Create staging buffer
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (padded_bytes_per_row * height) as _,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // Copy texture contents to the staging buffer
+        let encoder_desc = wgpu::CommandEncoderDescriptor { label: None };
+        let mut encoder = device.create_command_encoder(&encoder_desc);
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(Some(encoder.finish()));
+
+        // Wait for the copy and map the staging buffer
+        let slice = buffer.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        futures::executor::block_on(mapping)?;
+
+        // Strip row padding
+        let data = slice.get_mapped_range();
+        let mut pixels: Vec<u8> = data
+            .chunks_exact(padded_bytes_per_row as _)
+            .flat_map(|row| &row[..unpadded_bytes_per_row as _])
+            .copied()
+            .collect();
+        drop(data);
+        buffer.unmap();
+
+        // Swap red and blue of BGRA surfaces
+        if bgra {
+            pixels.chunks_exact_mut(4).for_each(|p| p.swap(0, 2));
+        }
+
+        Ok(RgbaImage::from_raw(width, height, pixels).unwrap())
+    }
+}
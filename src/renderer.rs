@@ -3,32 +3,72 @@
 //
 
 use crate::{
+    camera::Projection,
+    light::{Light, LightKind},
     mesh::{Index, IndexFormat, MeshBuffers, Vertex},
-    scene::Scene,
-    uniform::{MaterialUniform, TransformUniform, ViewProjUniform},
+    model::{Material, MaterialMaps, Shading, Texture},
+    scene::{ObjectId, Scene, SceneObject},
+    uniform::{
+        LightsUniform, MaterialUniform, ShadowUniform, TransformUniform, UniformBinding,
+        ViewProjUniform, MATERIAL_MAPS, MAX_LIGHTS,
+    },
+};
+use glam::{const_vec3, Mat4, Vec3, Vec4};
+use std::{
+    collections::{BTreeMap, HashMap},
+    iter,
+    num::NonZeroU32,
+    sync::{Arc, Weak},
 };
-use glam::Mat4;
 
 /// The Renderer
 ///
 /// Manages GPU specific objects and performs the rendering
 pub struct Renderer {
     view_proj: ViewProj,
+    projection: Projection,
+    surface_conf: wgpu::SurfaceConfiguration,
     forward_pass: ForwardPass,
+    shadow_pass: ShadowPass,
     transform_layout: wgpu::BindGroupLayout,
     material_layout: wgpu::BindGroupLayout,
+    material_sampler: wgpu::Sampler,
+    /// Bound in place of missing material maps
+    white_texture: wgpu::TextureView,
+    textures: TextureCache,
+    lights: Lights,
+}
+
+/// Texture views uploaded for materials, shared by every object using the same Texture
+///
+/// Keyed by the address of the shared Texture and whether it is sRGB encoded.
+/// The weak references keep the addresses from being reused while cached
+#[derive(Default)]
+struct TextureCache {
+    views: HashMap<(*const Texture, bool), (Weak<Texture>, wgpu::TextureView)>,
 }
 
 #[derive(Default)]
 pub struct RendererScene {
-    pub objects: Vec<RendererSceneObject>,
+    /// GPU resources of the scene objects, by id
+    pub objects: BTreeMap<ObjectId, RendererSceneObject>,
     pub view: Mat4,
 }
 
 pub struct RendererSceneObject {
     pub meshes: Vec<MeshBuffers>,
-    pub materials: Vec<wgpu::BindGroup>,
-    pub transform: wgpu::BindGroup,
+    pub materials: Vec<RendererMaterial>,
+    pub transform: UniformBinding,
+    /// Last written transform, used to bound the scene
+    pub world: Mat4,
+}
+
+pub struct RendererMaterial {
+    pub binding: UniformBinding,
+    /// Maps bound along with the uniform
+    pub maps: MaterialMaps,
+    /// Drawn with blending after all the opaque meshes
+    pub translucent: bool,
 }
 
 #[allow(dead_code)]
@@ -39,22 +79,60 @@ struct ViewProj {
     bind_group: wgpu::BindGroup,
 }
 
+/// Lights uniform along with the shadow maps of the lights casting shadows
+struct Lights {
+    layout: wgpu::BindGroupLayout,
+    shadow_layout: wgpu::BindGroupLayout,
+    binding: UniformBinding,
+    sampler: wgpu::Sampler,
+    shadow_maps: ShadowMaps,
+}
+
+/// Depth texture array with a layer per shadow casting light
+///
+/// Point lights take six layers, one per cube face, instead of a cube texture
+/// so that every light shares the same binding. Their filtering in `lights.glsl`
+/// picks the face of each sample to avoid seams
+struct ShadowMaps {
+    size: u32,
+    view: wgpu::TextureView,
+    layers: Vec<ShadowLayer>,
+}
+
+/// Shadow map of a single light or cube face, using `resolution` texels of its layer
+struct ShadowLayer {
+    view: wgpu::TextureView,
+    resolution: u32,
+    /// Stores linear distances to a point light rather than depth
+    linear: bool,
+    uniform: UniformBinding,
+}
+
 #[allow(dead_code)]
 struct ForwardPass {
     pipeline: wgpu::RenderPipeline,
+    /// Alpha blends translucent meshes without writing depth
+    blend_pipeline: wgpu::RenderPipeline,
     depth_texture_view: wgpu::TextureView,
+    /// Depth cleared to, depending on the projection
+    far_depth: f32,
+}
+
+struct ShadowPass {
+    pipeline: wgpu::RenderPipeline,
+    point_pipeline: wgpu::RenderPipeline,
 }
 
 impl Renderer {
-    pub fn new(device: &wgpu::Device, surface_conf: &wgpu::SurfaceConfiguration) -> Self {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        surface_conf: &wgpu::SurfaceConfiguration,
+    ) -> Self {
         // Setup view projetion uniform
+        let projection = Projection::default();
         let view_proj_data = ViewProjUniform {
-            proj: Mat4::perspective_lh(
-                (45.0f32).to_radians(),
-                surface_conf.width as f32 / surface_conf.height as f32,
-                0.1,
-                100.0,
-            ),
+            proj: projection.matrix(aspect_ratio(surface_conf)),
             ..Default::default()
         };
         let view_proj_layout = ViewProjUniform::layout(&device);
@@ -72,14 +150,52 @@ impl Renderer {
         let transform_layout = TransformUniform::layout(&device);
         let material_layout = MaterialUniform::layout(&device);
 
+        // Setup material textures
+        let material_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let white_texture = Texture::white().create_view(device, queue, false);
+
+        // Setup lights uniform and shadow maps, rewritten every frame
+        let lights_layout = LightsUniform::layout(device);
+        let lights_buffer = LightsUniform::default().create_buffer(device);
+        let shadow_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+        let shadow_layout = ShadowUniform::layout(device);
+        let shadow_maps = ShadowMaps::new(device, 1, 0, &shadow_layout);
+        let lights_bind_group = LightsUniform::create_bind_group(
+            device,
+            &lights_layout,
+            &lights_buffer,
+            &shadow_maps.view,
+            &shadow_sampler,
+        );
+
         // Setup forward pass
         let forward_pass = ForwardPass::new(
             device,
             surface_conf,
+            &projection,
             &view_proj_layout,
             &transform_layout,
             &material_layout,
+            &lights_layout,
         );
+        let shadow_pass = ShadowPass::new(device, &shadow_layout, &transform_layout);
 
         Renderer {
             view_proj: ViewProj {
@@ -88,57 +204,290 @@ impl Renderer {
                 layout: view_proj_layout,
                 bind_group: view_proj_bind_group,
             },
+            projection,
+            surface_conf: surface_conf.clone(),
             forward_pass,
+            shadow_pass,
             transform_layout,
             material_layout,
+            material_sampler,
+            white_texture,
+            textures: TextureCache::default(),
+            lights: Lights {
+                layout: lights_layout,
+                shadow_layout,
+                binding: UniformBinding {
+                    buffer: lights_buffer,
+                    bind_group: lights_bind_group,
+                },
+                sampler: shadow_sampler,
+                shadow_maps,
+            },
         }
     }
 
     pub fn resize(&mut self, device: &wgpu::Device, surface_conf: &wgpu::SurfaceConfiguration) {
-        // Recreate surface dependent passes
+        self.surface_conf = surface_conf.clone();
+        self.recreate_forward_pass(device);
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    /// Switches to the camera `projection`, recreating the forward pass
+    /// if its depth direction changes
+    pub fn set_projection(&mut self, device: &wgpu::Device, projection: Projection) {
+        let reversed = projection.reversed_z != self.projection.reversed_z;
+        self.projection = projection;
+        match reversed {
+            true => self.recreate_forward_pass(device),
+            false => self.update_projection(),
+        }
+    }
+
+    /// Recreates the surface and projection dependent passes
+    fn recreate_forward_pass(&mut self, device: &wgpu::Device) {
         self.forward_pass = ForwardPass::new(
             device,
-            surface_conf,
+            &self.surface_conf,
+            &self.projection,
             &self.view_proj.layout,
             &self.transform_layout,
             &self.material_layout,
+            &self.lights.layout,
         );
+        self.update_projection();
+    }
+
+    fn update_projection(&mut self) {
+        let aspect = aspect_ratio(&self.surface_conf);
+        self.view_proj.data.proj = self.projection.matrix(aspect);
+    }
+
+    pub fn create_scene(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &Scene,
+    ) -> RendererScene {
+        let objects = self.create_objects(device, queue, scene.objects.values());
+        let objects = scene.objects.ids().zip(objects).collect();
+        let view = scene.view;
+        RendererScene { objects, view }
+    }
+
+    pub fn create_object(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        object: &SceneObject,
+    ) -> RendererSceneObject {
+        let mut objects = self.create_objects(device, queue, iter::once(object));
+        objects.remove(0)
+    }
+
+    /// Creates the GPU resources of `objects`,
+    /// uploading only the textures not already used by other objects
+    pub fn create_objects<'a>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        objects: impl IntoIterator<Item = &'a SceneObject>,
+    ) -> Vec<RendererSceneObject> {
+        let objects = objects
+            .into_iter()
+            .map(|object| self.create_object_uncached(device, queue, object))
+            .collect();
+        self.textures.prune();
+        objects
     }
 
-    pub fn create_scene(&self, device: &wgpu::Device, scene: &Scene) -> RendererScene {
-        let objects = scene
-            .objects
+    fn create_object_uncached(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        object: &SceneObject,
+    ) -> RendererSceneObject {
+        let meshes = object
+            .meshes
             .iter()
-            .map(|object| {
-                let meshes = object
-                    .meshes
-                    .iter()
-                    .map(|m| m.create_buffers(device))
-                    .collect();
-                let materials = object
-                    .materials
-                    .iter()
-                    .map(|m| {
-                        MaterialUniform {
-                            albedo: m.unwrap_or_default().0,
-                        }
-                        .create_bind_group(device, &self.material_layout)
-                    })
-                    .collect();
-                let transform = TransformUniform {
-                    model: object.transform,
-                }
-                .create_bind_group(device, &self.transform_layout);
-                RendererSceneObject {
-                    meshes,
-                    materials,
-                    transform,
+            .map(|m| m.create_buffers(device))
+            .collect();
+        let materials = object
+            .materials
+            .iter()
+            .map(|m| self.create_material(device, queue, m.as_ref()))
+            .collect();
+        let transform = TransformUniform {
+            model: object.world,
+        }
+        .create_binding(device, &self.transform_layout);
+        RendererSceneObject {
+            meshes,
+            materials,
+            transform,
+            world: object.world,
+        }
+    }
+
+    /// Writes the new transform of `object` in place
+    pub fn update_transform(
+        &self,
+        queue: &wgpu::Queue,
+        object: &mut RendererSceneObject,
+        transform: Mat4,
+    ) {
+        TransformUniform { model: transform }.write(queue, &object.transform);
+        object.world = transform;
+    }
+
+    /// Writes `lights` to the lights uniform, fitting the shadow maps
+    /// of the lights casting shadows to the bounds of `scene`
+    pub fn update_lights(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &RendererScene,
+        lights: &[Light],
+    ) {
+        let bounds = scene_bounds(scene);
+        let casters: Vec<_> = lights
+            .iter()
+            .take(MAX_LIGHTS)
+            .enumerate()
+            .filter_map(|(i, light)| {
+                let shadow = light.shadow?;
+                let views = light_views(light, bounds?);
+                match views.is_empty() {
+                    true => None,
+                    false => Some((i, shadow, views)),
                 }
             })
             .collect();
+        let count = casters.iter().map(|c| c.2.len()).sum();
 
-        let view = scene.view;
-        RendererScene { objects, view }
+        // Reallocate the shadow maps once they no longer match the casters
+        let max_size = device.limits().max_texture_dimension_2d;
+        let size = casters
+            .iter()
+            .map(|c| c.1.resolution.clamp(1, max_size))
+            .max()
+            .unwrap_or(1);
+        let maps = &self.lights.shadow_maps;
+        if maps.size != size || maps.layers.len() != count {
+            let maps = ShadowMaps::new(device, size, count, &self.lights.shadow_layout);
+            self.lights.binding.bind_group = LightsUniform::create_bind_group(
+                device,
+                &self.lights.layout,
+                &self.lights.binding.buffer,
+                &maps.view,
+                &self.lights.sampler,
+            );
+            self.lights.shadow_maps = maps;
+        }
+
+        // Place every view of the casters in its own layer
+        let mut data = LightsUniform::new(lights);
+        let mut layer = 0;
+        for (i, shadow, views) in casters {
+            let resolution = shadow.resolution.clamp(1, size);
+            let linear = matches!(lights[i].kind, LightKind::Point { .. });
+            let (view, proj) = views[0];
+            data.lights[i].view_proj = proj * view;
+            data.lights[i].shadow = Vec4::new(
+                layer as f32,
+                shadow.bias,
+                shadow.slope_bias,
+                resolution as f32 / size as f32,
+            );
+
+            for (view, proj) in views {
+                let target = &mut self.lights.shadow_maps.layers[layer];
+                target.resolution = resolution;
+                target.linear = linear;
+                let uniform = ShadowUniform {
+                    view,
+                    proj,
+                    range: lights[i].clamped_range(),
+                    ..Default::default()
+                };
+                uniform.write(queue, &target.uniform);
+                layer += 1;
+            }
+        }
+        data.write(queue, &self.lights.binding);
+    }
+
+    /// Writes the material of the `index`th mesh of `object` in place,
+    /// rebinding it only when its maps changed. Panics if `object` has no such mesh
+    pub fn update_material(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        object: &mut RendererSceneObject,
+        index: usize,
+        material: Option<&Material>,
+    ) {
+        let maps = material.map(|m| m.maps.clone()).unwrap_or_default();
+        let target = &mut object.materials[index];
+        if !same_maps(&target.maps, &maps) {
+            self.upload_maps(device, queue, &maps);
+            let views = self.material_views(&maps);
+            target.binding.bind_group = MaterialUniform::create_bind_group(
+                device,
+                &self.material_layout,
+                &target.binding.buffer,
+                &self.material_sampler,
+                &views,
+            );
+            target.maps = maps;
+            self.textures.prune();
+        }
+        material_uniform(material).write(queue, &target.binding);
+        target.translucent = translucent(material);
+    }
+
+    fn create_material(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        material: Option<&Material>,
+    ) -> RendererMaterial {
+        let maps = material.map(|m| m.maps.clone()).unwrap_or_default();
+        self.upload_maps(device, queue, &maps);
+        let views = self.material_views(&maps);
+        let binding = material_uniform(material).create_binding(
+            device,
+            &self.material_layout,
+            &self.material_sampler,
+            &views,
+        );
+        RendererMaterial {
+            binding,
+            maps,
+            translucent: translucent(material),
+        }
+    }
+
+    /// Uploads the `maps` not cached yet
+    fn upload_maps(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, maps: &MaterialMaps) {
+        for (texture, srgb) in &map_slots(maps) {
+            if let Some(texture) = texture {
+                self.textures.upload(device, queue, texture, *srgb);
+            }
+        }
+    }
+
+    /// Views of the uploaded `maps` in binding order, missing maps are replaced by a white texture
+    fn material_views(&self, maps: &MaterialMaps) -> [&wgpu::TextureView; MATERIAL_MAPS] {
+        let mut views = [&self.white_texture; MATERIAL_MAPS];
+        for (view, (texture, srgb)) in views.iter_mut().zip(&map_slots(maps)) {
+            if let Some(texture) = texture {
+                *view = self.textures.get(texture, *srgb);
+            }
+        }
+        views
     }
 
     pub fn render(
@@ -155,13 +504,24 @@ impl Renderer {
             0,
             bytemuck::bytes_of(&ViewProjUniform {
                 view: scene.view,
+                eye: scene.view.inverse().w_axis,
                 ..vp.data
             }),
         );
 
+        // Render the shadow maps
+        for layer in &self.lights.shadow_maps.layers {
+            self.shadow_pass.execute(encoder, layer, scene);
+        }
+
         // Make forward pass
-        self.forward_pass
-            .execute(encoder, view, &vp.bind_group, scene);
+        self.forward_pass.execute(
+            encoder,
+            view,
+            &vp.bind_group,
+            &self.lights.binding.bind_group,
+            scene,
+        );
     }
 }
 
@@ -169,9 +529,11 @@ impl ForwardPass {
     pub fn new(
         device: &wgpu::Device,
         surface_conf: &wgpu::SurfaceConfiguration,
+        projection: &Projection,
         view_proj_layout: &wgpu::BindGroupLayout,
         transform_layout: &wgpu::BindGroupLayout,
         material_layout: &wgpu::BindGroupLayout,
+        lights_layout: &wgpu::BindGroupLayout,
     ) -> Self {
         let vsrc = include_shader!("forward.vert");
         let fsrc = include_shader!("forward.frag");
@@ -196,36 +558,49 @@ impl ForwardPass {
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: None,
-            bind_group_layouts: &[view_proj_layout, transform_layout, material_layout],
+            bind_group_layouts: &[
+                view_proj_layout,
+                transform_layout,
+                material_layout,
+                lights_layout,
+            ],
             push_constant_ranges: &[],
         });
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: None,
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &vshader,
-                entry_point: "main",
-                buffers: &[Vertex::buffer_layout()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &fshader,
-                entry_point: "main",
-                targets: &[surface_conf.format.into()],
-            }),
-            primitive: wgpu::PrimitiveState::default(),
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: depth_format,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState::default(),
-        });
+        let create_pipeline = |blend: Option<wgpu::BlendState>| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: None,
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &vshader,
+                    entry_point: "main",
+                    buffers: &[Vertex::buffer_layout()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &fshader,
+                    entry_point: "main",
+                    targets: &[wgpu::ColorTargetState {
+                        format: surface_conf.format,
+                        blend,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: depth_format,
+                    depth_write_enabled: blend.is_none(),
+                    depth_compare: projection.depth_compare(),
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+            })
+        };
 
         ForwardPass {
-            pipeline,
+            pipeline: create_pipeline(None),
+            blend_pipeline: create_pipeline(Some(wgpu::BlendState::ALPHA_BLENDING)),
             depth_texture_view,
+            far_depth: projection.far_depth(),
         }
     }
 
@@ -234,6 +609,7 @@ impl ForwardPass {
         encoder: &mut wgpu::CommandEncoder,
         color_texture_view: &wgpu::TextureView,
         view_proj_bind_group: &wgpu::BindGroup,
+        lights_bind_group: &wgpu::BindGroup,
         scene: &RendererScene,
     ) {
         let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -249,20 +625,119 @@ impl ForwardPass {
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                 view: &self.depth_texture_view,
                 depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(1.0),
+                    load: wgpu::LoadOp::Clear(self.far_depth),
                     store: true,
                 }),
                 stencil_ops: None,
             }),
         });
 
-        rpass.set_pipeline(&self.pipeline);
         rpass.set_bind_group(0, &view_proj_bind_group, &[]);
+        rpass.set_bind_group(3, lights_bind_group, &[]);
+
+        // Draw opaque meshes first, then blend translucent ones over them unsorted
+        for (pipeline, translucent) in [(&self.pipeline, false), (&self.blend_pipeline, true)] {
+            rpass.set_pipeline(pipeline);
+            for o in scene.objects.values() {
+                rpass.set_bind_group(1, &o.transform.bind_group, &[]);
+                for (i, m) in o.meshes.iter().enumerate() {
+                    let material = &o.materials[i];
+                    if material.translucent != translucent {
+                        continue;
+                    }
+                    rpass.set_bind_group(2, &material.binding.bind_group, &[]);
+                    rpass.set_vertex_buffer(0, m.vbuf.slice(..));
+                    rpass.set_index_buffer(m.ibuf.slice(..), Index::format());
+                    rpass.draw_indexed(0..m.nelems, 0, 0..1);
+                }
+            }
+        }
+    }
+}
+
+impl ShadowPass {
+    pub fn new(
+        device: &wgpu::Device,
+        shadow_layout: &wgpu::BindGroupLayout,
+        transform_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let vsrc = include_shader!("shadow.vert");
+        let fsrc = include_shader!("shadow_point.frag");
+        let vshader = device.create_shader_module(&vsrc);
+        let fshader = device.create_shader_module(&fsrc);
 
-        for o in &scene.objects {
-            rpass.set_bind_group(1, &o.transform, &[]);
-            for (i, m) in o.meshes.iter().enumerate() {
-                rpass.set_bind_group(2, &o.materials[i], &[]);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[shadow_layout, transform_layout],
+            push_constant_ranges: &[],
+        });
+
+        // Depth only, point lights write their linear distance instead
+        let create_pipeline = |fragment| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: None,
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &vshader,
+                    entry_point: "main",
+                    buffers: &[Vertex::buffer_layout()],
+                },
+                fragment,
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: ShadowMaps::FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::LessEqual,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+            })
+        };
+        let pipeline = create_pipeline(None);
+        let point_pipeline = create_pipeline(Some(wgpu::FragmentState {
+            module: &fshader,
+            entry_point: "main",
+            targets: &[],
+        }));
+
+        ShadowPass {
+            pipeline,
+            point_pipeline,
+        }
+    }
+
+    fn execute(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        layer: &ShadowLayer,
+        scene: &RendererScene,
+    ) {
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &layer.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        // Only the corner of the layer matching the light resolution is used
+        let res = layer.resolution as f32;
+        rpass.set_viewport(0.0, 0.0, res, res, 0.0, 1.0);
+        rpass.set_pipeline(match layer.linear {
+            true => &self.point_pipeline,
+            false => &self.pipeline,
+        });
+        rpass.set_bind_group(0, &layer.uniform.bind_group, &[]);
+
+        for o in scene.objects.values() {
+            rpass.set_bind_group(1, &o.transform.bind_group, &[]);
+            for m in &o.meshes {
                 rpass.set_vertex_buffer(0, m.vbuf.slice(..));
                 rpass.set_index_buffer(m.ibuf.slice(..), Index::format());
                 rpass.draw_indexed(0..m.nelems, 0, 0..1);
@@ -270,3 +745,220 @@ impl ForwardPass {
         }
     }
 }
+
+impl ShadowMaps {
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+    /// Creates `count` layers of `size` texels squared, always allocating at least one
+    /// so the forward pass has something to bind
+    fn new(
+        device: &wgpu::Device,
+        size: u32,
+        count: usize,
+        shadow_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: count.max(1) as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            label: None,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let layers = (0..count)
+            .map(|i| ShadowLayer {
+                view: texture.create_view(&wgpu::TextureViewDescriptor {
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: i as u32,
+                    array_layer_count: NonZeroU32::new(1),
+                    ..Default::default()
+                }),
+                resolution: size,
+                linear: false,
+                uniform: ShadowUniform::default().create_binding(device, shadow_layout),
+            })
+            .collect();
+
+        ShadowMaps { size, view, layers }
+    }
+}
+
+/// World space bounds of all the meshes in the scene
+fn scene_bounds(scene: &RendererScene) -> Option<(Vec3, Vec3)> {
+    scene
+        .objects
+        .values()
+        .flat_map(|o| {
+            o.meshes.iter().filter(|m| m.nelems > 0).flat_map(move |m| {
+                let (min, max) = m.bbox;
+                (0..8).map(move |i| {
+                    let corner = Vec3::new(
+                        if i & 1 == 0 { min.x } else { max.x },
+                        if i & 2 == 0 { min.y } else { max.y },
+                        if i & 4 == 0 { min.z } else { max.z },
+                    );
+                    o.world.transform_point3(corner)
+                })
+            })
+        })
+        .fold(None, |b, p| match b {
+            Some((min, max)) => Some((p.min(min), p.max(max))),
+            None => Some((p, p)),
+        })
+}
+
+/// Cube face directions and up vectors, matching `CUBE_FACES` in `lights.glsl`
+const CUBE_FACES: [(Vec3, Vec3); 6] = [
+    (Vec3::X, Vec3::Y),
+    (const_vec3!([-1.0, 0.0, 0.0]), Vec3::Y),
+    (Vec3::Y, const_vec3!([0.0, 0.0, -1.0])),
+    (const_vec3!([0.0, -1.0, 0.0]), Vec3::Z),
+    (Vec3::Z, Vec3::Y),
+    (const_vec3!([0.0, 0.0, -1.0]), Vec3::Y),
+];
+
+/// Views and projections of a shadow casting light, one per shadow map layer.
+/// Directional lights cover all of `bounds`, spot lights their cone
+/// and point lights a cube face each, up to their range
+fn light_views(light: &Light, bounds: (Vec3, Vec3)) -> Vec<(Mat4, Mat4)> {
+    // Any up vector not parallel to the light direction will do
+    let up = |dir: Vec3| if dir.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+    let range = light.clamped_range();
+
+    match light.kind {
+        LightKind::Directional { direction } => {
+            let dir = match direction.try_normalize() {
+                Some(dir) => dir,
+                None => return vec![],
+            };
+            let center = (bounds.0 + bounds.1) / 2.0;
+            let radius = ((bounds.1 - bounds.0).length() / 2.0).max(0.01);
+            let view = Mat4::look_at_lh(center - dir * radius, center, up(dir));
+            let proj = Mat4::orthographic_lh(-radius, radius, -radius, radius, 0.0, 2.0 * radius);
+            vec![(view, proj)]
+        }
+        LightKind::Spot {
+            position,
+            direction,
+            outer_angle,
+            ..
+        } => {
+            let dir = match direction.try_normalize() {
+                Some(dir) => dir,
+                None => return vec![],
+            };
+            let fov = (2.0 * outer_angle).clamp(0.01, 179f32.to_radians());
+            let view = Mat4::look_at_lh(position, position + dir, up(dir));
+            let proj = Mat4::perspective_lh(fov, 1.0, 0.05, range);
+            vec![(view, proj)]
+        }
+        LightKind::Point { position } => {
+            let proj = Mat4::perspective_lh(std::f32::consts::FRAC_PI_2, 1.0, 0.05, range);
+            CUBE_FACES
+                .iter()
+                .map(|&(dir, up)| (Mat4::look_at_lh(position, position + dir, up), proj))
+                .collect()
+        }
+    }
+}
+
+impl TextureCache {
+    /// Uploads `texture` unless it is already cached
+    fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &Arc<Texture>,
+        srgb: bool,
+    ) {
+        self.views
+            .entry((Arc::as_ptr(texture), srgb))
+            .or_insert_with(|| {
+                let view = texture.create_view(device, queue, srgb);
+                (Arc::downgrade(texture), view)
+            });
+    }
+
+    /// View of an uploaded `texture`
+    fn get(&self, texture: &Arc<Texture>, srgb: bool) -> &wgpu::TextureView {
+        &self.views[&(Arc::as_ptr(texture), srgb)].1
+    }
+
+    /// Drops the views of textures no longer used by any material
+    fn prune(&mut self) {
+        self.views
+            .retain(|_, (texture, _)| texture.strong_count() > 0);
+    }
+}
+
+/// Maps in binding order along with whether they are sRGB encoded
+fn map_slots(maps: &MaterialMaps) -> [(Option<&Arc<Texture>>, bool); MATERIAL_MAPS] {
+    [
+        (maps.base_color.as_ref(), true),
+        (maps.metallic_roughness.as_ref(), false),
+        (maps.normal.as_ref(), false),
+        (maps.occlusion.as_ref(), false),
+        (maps.emissive.as_ref(), true),
+    ]
+}
+
+/// Whether both sets of maps reference the same textures
+fn same_maps(a: &MaterialMaps, b: &MaterialMaps) -> bool {
+    map_slots(a)
+        .iter()
+        .zip(&map_slots(b))
+        .all(|((a, _), (b, _))| match (a, b) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        })
+}
+
+/// Translucent materials are drawn after the opaque ones
+fn translucent(material: Option<&Material>) -> bool {
+    matches!(material, Some(m) if m.dissolve < 1.0)
+}
+
+fn aspect_ratio(surface_conf: &wgpu::SurfaceConfiguration) -> f32 {
+    surface_conf.width as f32 / surface_conf.height.max(1) as f32
+}
+
+/// Meshes without a material use the default one
+fn material_uniform(material: Option<&Material>) -> MaterialUniform {
+    let default = Material::default();
+    let m = material.unwrap_or(&default);
+    MaterialUniform {
+        base_color: m.base_color,
+        metallic: m.metallic,
+        emissive: m.emissive,
+        roughness: m.roughness,
+        // MTL illumination models below 2 have no highlights
+        specular: match m.illumination_model {
+            Some(0) | Some(1) => Vec3::ZERO,
+            _ => m.specular,
+        },
+        // Files without `Ns` give 0, and pow(0, 0) is undefined in the highlight
+        shininess: m.shininess.max(1.0),
+        ambient: m.ambient,
+        dissolve: m.dissolve,
+        normal_scale: match m.maps.normal {
+            Some(_) => m.normal_scale,
+            None => 0.0,
+        },
+        occlusion_strength: m.occlusion_strength,
+        shading: match m.shading {
+            Shading::MetallicRoughness => 0,
+            Shading::BlinnPhong => 1,
+        },
+        _pad: 0.0,
+    }
+}
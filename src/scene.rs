@@ -2,18 +2,429 @@
 // scene.rs
 //
 
-use super::mesh::Mesh;
-use glam::{Mat4, Vec3};
+use super::{
+    camera::Projection,
+    light::Light,
+    mesh::Mesh,
+    model::{ImportOptions, Material, Model, ModelError},
+    slot_map::{SlotId, SlotMap},
+};
+use glam::{Mat4, Quat, Vec3};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt, io, path::PathBuf};
 
+/// Id of an object in `Scene::objects`, valid until the object is removed
+pub type ObjectId = SlotId;
+
+/// Scene graph
+///
+/// Objects are kept in a slot map and form a hierarchy through their parent links.
+/// World transforms are cached in every object and refreshed by `Scene::update_transforms`.
+/// Scenes can be stored in RON files, see `Scene::load`
 #[derive(Default, Debug)]
 pub struct Scene {
-    pub objects: Vec<SceneObject>,
+    /// Objects whose parent is missing are treated as roots,
+    /// `add`, `remove` and `set_parent` keep the parent links valid
+    pub objects: SlotMap<SceneObject>,
     pub view: Mat4,
+    /// Initial camera placement, applied by `Engine::set_scene`
+    pub camera: Option<SceneCamera>,
+    pub lights: Vec<Light>,
 }
 
 #[derive(Default, Debug)]
 pub struct SceneObject {
+    pub name: String,
+    pub parent: Option<ObjectId>,
+    /// Transform relative to the parent
+    pub transform: Transform,
+    /// Cached transform relative to the scene root
+    pub world: Mat4,
+    /// Model node the meshes were instantiated from
+    pub source: Option<ModelSource>,
     pub meshes: Vec<Mesh>,
-    pub materials: Vec<Option<(Vec3,)>>,
-    pub transform: Mat4,
+    pub materials: Vec<Option<Material>>,
+}
+
+/// Reference to a node of a model file
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModelSource {
+    pub path: PathBuf,
+    /// Index into `Model::nodes`
+    pub node: usize,
+    /// Settings the model is imported with
+    #[serde(default)]
+    pub options: ImportOptions,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SceneCamera {
+    pub position: Vec3,
+    pub target: Vec3,
+    /// Keeps the projection of the engine camera when left out
+    #[serde(default)]
+    pub projection: Option<Projection>,
+}
+
+/// Translation, rotation and scale, applied in reverse order
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Scene {
+    /// Adds the nodes of `model` under a new root object placed at `transform`,
+    /// with the materials of their meshes resolved by name.
+    /// Returns the id of the root object
+    pub fn add_model(&mut self, model: &Model, transform: Mat4) -> ObjectId {
+        let root = self.objects.insert(SceneObject {
+            name: model.name.clone(),
+            transform: Transform::from_matrix(transform),
+            ..Default::default()
+        });
+
+        // Nodes come after their parents
+        let mut ids = Vec::with_capacity(model.nodes.len());
+        for (i, node) in model.nodes.iter().enumerate() {
+            let meshes = node.meshes.iter().map(|&i| model.meshes[i].clone());
+            let materials = node.meshes.iter().map(|&i| model.mesh_material(i).cloned());
+
+            let id = self.objects.insert(SceneObject {
+                name: node.name.clone(),
+                parent: Some(node.parent.map_or(root, |p| ids[p])),
+                transform: Transform::from_matrix(node.transform),
+                source: model.path.clone().map(|path| ModelSource {
+                    path,
+                    node: i,
+                    options: model.options,
+                }),
+                meshes: meshes.collect(),
+                materials: materials.collect(),
+                ..Default::default()
+            });
+            ids.push(id);
+        }
+
+        self.update_transforms();
+        root
+    }
+
+    /// Adds `object` and returns its id.
+    /// Fails if its parent was removed
+    pub fn add(&mut self, object: SceneObject) -> Result<ObjectId, SceneError> {
+        if let Some(parent) = object.parent {
+            self.object(parent)?;
+        }
+        Ok(self.objects.insert(object))
+    }
+
+    /// Object `id`, or an error if it was removed
+    pub fn object(&self, id: ObjectId) -> Result<&SceneObject, SceneError> {
+        self.objects.get(id).ok_or(SceneError::UnknownObject(id))
+    }
+
+    /// Object `id`, or an error if it was removed
+    pub fn object_mut(&mut self, id: ObjectId) -> Result<&mut SceneObject, SceneError> {
+        self.objects
+            .get_mut(id)
+            .ok_or(SceneError::UnknownObject(id))
+    }
+
+    /// Finds the first object named `name`
+    pub fn find(&self, name: &str) -> Option<ObjectId> {
+        self.objects
+            .iter()
+            .find(|(_, o)| o.name == name)
+            .map(|(id, _)| id)
+    }
+
+    /// Iterates over the direct children of `id`
+    pub fn children(&self, id: ObjectId) -> impl Iterator<Item = ObjectId> + '_ {
+        self.objects
+            .iter()
+            .filter(move |(_, o)| o.parent == Some(id))
+            .map(|(id, _)| id)
+    }
+
+    /// Attaches `id` to `parent`, keeping its local transform.
+    /// Fails and leaves the hierarchy untouched if either object was removed
+    /// or if that would create a cycle
+    pub fn set_parent(&mut self, id: ObjectId, parent: Option<ObjectId>) -> Result<(), SceneError> {
+        self.object(id)?;
+        let mut ancestor = parent;
+        while let Some(a) = ancestor {
+            if a == id {
+                return Err(SceneError::InvalidParent(id));
+            }
+            ancestor = self.object(a)?.parent;
+        }
+        self.object_mut(id)?.parent = parent;
+        Ok(())
+    }
+
+    /// Removes `id` and returns it. Its children are attached to its parent,
+    /// keeping their world transforms. The ids of other objects are unaffected
+    pub fn remove(&mut self, id: ObjectId) -> Result<SceneObject, SceneError> {
+        let removed = self
+            .objects
+            .remove(id)
+            .ok_or(SceneError::UnknownObject(id))?;
+        for (_, o) in self.objects.iter_mut() {
+            if o.parent == Some(id) {
+                o.transform =
+                    Transform::from_matrix(removed.transform.matrix() * o.transform.matrix());
+                o.parent = removed.parent;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Recomputes the world transforms of all objects from their local transforms.
+    /// Returns the ids of the objects whose world transform changed
+    pub fn update_transforms(&mut self) -> Vec<ObjectId> {
+        let mut worlds: Vec<Option<Mat4>> = vec![None; self.objects.capacity()];
+        let ids: Vec<_> = self.objects.ids().collect();
+        for &id in &ids {
+            self.world_transform(id, &mut worlds);
+        }
+
+        let mut changed = vec![];
+        for (id, o) in self.objects.iter_mut() {
+            let world = worlds[id.index()].unwrap();
+            if o.world != world {
+                o.world = world;
+                changed.push(id);
+            }
+        }
+        changed
+    }
+
+    /// Computes the world transform of `id`, memoizing it and its ancestors in `worlds`.
+    /// A missing object stands for the scene root
+    fn world_transform(&self, id: ObjectId, worlds: &mut [Option<Mat4>]) -> Mat4 {
+        // Check the id before its slot, which may hold a newer object
+        let object = match self.objects.get(id) {
+            Some(object) => object,
+            None => return Mat4::IDENTITY,
+        };
+        if let Some(world) = worlds[id.index()] {
+            return world;
+        }
+        let local = object.transform.matrix();
+        let world = match object.parent {
+            Some(parent) => self.world_transform(parent, worlds) * local,
+            None => local,
+        };
+        worlds[id.index()] = Some(world);
+        world
+    }
+}
+
+/// Errors reported while loading or saving a Scene
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    Parse(ron::Error),
+    Serialize(ron::Error),
+    /// A model referenced by the scene could not be loaded
+    Model(ModelError),
+    /// An object refers to a node the model does not have
+    MissingNode(ModelSource),
+    /// An object has a parent that does not exist or is its own descendant
+    InvalidParent(ObjectId),
+    /// An object id refers to an object that was removed
+    UnknownObject(ObjectId),
+    /// An object has no mesh at the given index
+    InvalidMaterialIndex(ObjectId, usize),
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Transform {
+        translation: Vec3::ZERO,
+        rotation: Quat::IDENTITY,
+        scale: Vec3::ONE,
+    };
+
+    /// Decomposes an affine matrix, shear is lost
+    pub fn from_matrix(matrix: Mat4) -> Self {
+        let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
+        Transform {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::IDENTITY
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(e) => write!(f, "Failed to access scene file: {}", e),
+            SceneError::Parse(e) => write!(f, "Failed to parse scene: {}", e),
+            SceneError::Serialize(e) => write!(f, "Failed to serialize scene: {}", e),
+            SceneError::Model(e) => write!(f, "Failed to load scene model: {}", e),
+            SceneError::MissingNode(source) => write!(
+                f,
+                "Model {} has no node {}",
+                source.path.display(),
+                source.node
+            ),
+            SceneError::InvalidParent(id) => write!(f, "Object {} has an invalid parent", id),
+            SceneError::UnknownObject(id) => write!(f, "Object {} does not exist", id),
+            SceneError::InvalidMaterialIndex(id, i) => {
+                write!(f, "Object {} has no mesh {}", id, i)
+            }
+        }
+    }
+}
+
+impl Error for SceneError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SceneError::Io(e) => Some(e),
+            SceneError::Parse(e) => Some(e),
+            SceneError::Serialize(e) => Some(e),
+            SceneError::Model(e) => Some(e),
+            SceneError::MissingNode(_)
+            | SceneError::InvalidParent(_)
+            | SceneError::UnknownObject(_)
+            | SceneError::InvalidMaterialIndex(..) => None,
+        }
+    }
+}
+
+impl From<io::Error> for SceneError {
+    fn from(e: io::Error) -> Self {
+        SceneError::Io(e)
+    }
+}
+
+impl From<ModelError> for SceneError {
+    fn from(e: ModelError) -> Self {
+        SceneError::Model(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translated(scene: &mut Scene, name: &str, parent: Option<ObjectId>, x: f32) -> ObjectId {
+        scene.objects.insert(SceneObject {
+            name: name.to_owned(),
+            parent,
+            transform: Transform {
+                translation: Vec3::new(x, 0.0, 0.0),
+                ..Transform::IDENTITY
+            },
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn set_parent_rejects_cycles() {
+        let mut scene = Scene::default();
+        let a = translated(&mut scene, "a", None, 0.0);
+        let b = translated(&mut scene, "b", Some(a), 0.0);
+        let c = translated(&mut scene, "c", Some(b), 0.0);
+
+        assert!(matches!(
+            scene.set_parent(a, Some(c)),
+            Err(SceneError::InvalidParent(id)) if id == a
+        ));
+        assert!(matches!(
+            scene.set_parent(a, Some(a)),
+            Err(SceneError::InvalidParent(_))
+        ));
+        assert_eq!(scene.object(a).unwrap().parent, None);
+
+        scene.set_parent(c, Some(a)).unwrap();
+        assert_eq!(scene.object(c).unwrap().parent, Some(a));
+        assert_eq!(scene.children(a).collect::<Vec<_>>(), vec![b, c]);
+    }
+
+    #[test]
+    fn world_transforms_compose_parents() {
+        let mut scene = Scene::default();
+        let a = translated(&mut scene, "a", None, 1.0);
+        let b = translated(&mut scene, "b", Some(a), 2.0);
+        let c = translated(&mut scene, "c", Some(b), 4.0);
+        assert_eq!(scene.update_transforms(), vec![a, b, c]);
+        let x = |scene: &Scene, id| scene.object(id).unwrap().world.w_axis.x;
+        assert_eq!(x(&scene, c), 7.0);
+
+        // Only the moved object and its descendants change
+        assert!(scene.update_transforms().is_empty());
+        scene.object_mut(b).unwrap().transform.translation.x = 3.0;
+        assert_eq!(scene.update_transforms(), vec![b, c]);
+        assert_eq!(x(&scene, c), 8.0);
+    }
+
+    #[test]
+    fn remove_keeps_other_ids_and_world_transforms() {
+        let mut scene = Scene::default();
+        let a = translated(&mut scene, "a", None, 1.0);
+        let b = translated(&mut scene, "b", Some(a), 2.0);
+        let c = translated(&mut scene, "c", Some(b), 4.0);
+        scene.update_transforms();
+
+        assert_eq!(scene.remove(b).unwrap().name, "b");
+        assert!(matches!(scene.remove(b), Err(SceneError::UnknownObject(_))));
+        assert!(scene.update_transforms().is_empty());
+        assert_eq!(scene.object(c).unwrap().parent, Some(a));
+        assert_eq!(scene.object(c).unwrap().world.w_axis.x, 7.0);
+
+        // Reusing the slot of b does not revive its id
+        let d = translated(&mut scene, "d", None, 0.0);
+        assert_ne!(b, d);
+        assert!(scene.object_mut(b).is_err());
+        assert!(scene.set_parent(c, Some(b)).is_err());
+        assert_eq!(scene.find("d"), Some(d));
+    }
+
+    #[test]
+    fn missing_parents_are_roots() {
+        let mut scene = Scene::default();
+        let a = translated(&mut scene, "a", None, 1.0);
+        let b = translated(&mut scene, "b", Some(a), 2.0);
+        scene.objects.remove(a);
+
+        // The new object takes the slot of the missing parent
+        let c = translated(&mut scene, "c", None, 4.0);
+        assert_eq!(scene.update_transforms(), vec![c, b]);
+        assert_eq!(scene.object(b).unwrap().world.w_axis.x, 2.0);
+        assert_eq!(scene.object(c).unwrap().world.w_axis.x, 4.0);
+    }
+
+    #[test]
+    fn add_rejects_removed_parents() {
+        let mut scene = Scene::default();
+        let a = translated(&mut scene, "a", None, 0.0);
+        let object = |parent| SceneObject {
+            parent: Some(parent),
+            ..Default::default()
+        };
+        let b = scene.add(object(a)).unwrap();
+        assert_eq!(scene.object(b).unwrap().parent, Some(a));
+
+        scene.remove(a).unwrap();
+        assert!(matches!(
+            scene.add(object(a)),
+            Err(SceneError::UnknownObject(id)) if id == a
+        ));
+        assert_eq!(scene.objects.len(), 1);
+    }
 }
@@ -0,0 +1,296 @@
+//
+// scene_file.rs
+//
+
+use super::{
+    light::Light,
+    model::{Material, Model},
+    scene::{ModelSource, Scene, SceneCamera, SceneError, SceneObject, Transform},
+    slot_map::SlotMap,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// Contents of a scene file
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct SceneFile {
+    objects: Vec<ObjectFile>,
+    camera: Option<SceneCamera>,
+    lights: Vec<Light>,
+}
+
+/// Object of a scene file, parents refer to positions in `SceneFile::objects`
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ObjectFile {
+    name: String,
+    parent: Option<usize>,
+    transform: Transform,
+    source: Option<ModelSource>,
+    materials: Vec<Option<Material>>,
+}
+
+impl Scene {
+    /// Loads a scene from a RON file.
+    ///
+    /// Meshes are instantiated from the model nodes the objects refer to.
+    /// Model paths are relative to the directory of the scene file.
+    /// Materials left out of the file are taken from the model
+    pub fn load(path: &Path) -> Result<Self, SceneError> {
+        let src = fs::read_to_string(path)?;
+        let mut file: SceneFile = ron::from_str(&src).map_err(SceneError::Parse)?;
+        let dir = parent_dir(path);
+        for source in file.objects.iter_mut().filter_map(|o| o.source.as_mut()) {
+            source.path = dir.join(&source.path);
+        }
+
+        // Objects get ids in file order, so parents map to the ids at their positions
+        let parents: Vec<_> = file.objects.iter().map(|o| o.parent).collect();
+        let mut scene = Scene {
+            objects: SlotMap::new(),
+            camera: file.camera,
+            lights: file.lights,
+            ..Default::default()
+        };
+        let ids: Vec<_> = file
+            .objects
+            .into_iter()
+            .map(|o| {
+                scene.objects.insert(SceneObject {
+                    name: o.name,
+                    transform: o.transform,
+                    source: o.source,
+                    materials: o.materials,
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        // Reject dangling parents and cycles before anything walks the hierarchy
+        let count = ids.len();
+        for (i, &id) in ids.iter().enumerate() {
+            let mut ancestor = parents[i];
+            for _ in 0..count {
+                match ancestor {
+                    Some(a) if a < count && a != i => ancestor = parents[a],
+                    Some(_) => return Err(SceneError::InvalidParent(id)),
+                    None => break,
+                }
+            }
+            if ancestor.is_some() {
+                return Err(SceneError::InvalidParent(id));
+            }
+            scene.objects.get_mut(id).unwrap().parent = parents[i].map(|p| ids[p]);
+        }
+
+        // Instantiate meshes, loading every referenced model once per import settings
+        let mut models: Vec<Model> = vec![];
+        for (_, object) in scene.objects.iter_mut() {
+            let source = match &object.source {
+                Some(source) => source,
+                None => continue,
+            };
+            let loaded = models
+                .iter()
+                .position(|m| m.path.as_ref() == Some(&source.path) && m.options == source.options);
+            let model = match loaded {
+                Some(i) => &models[i],
+                None => {
+                    models.push(Model::from_path(&source.path, &source.options)?);
+                    models.last().unwrap()
+                }
+            };
+            let node = model
+                .nodes
+                .get(source.node)
+                .ok_or_else(|| SceneError::MissingNode(source.clone()))?;
+
+            object.meshes = node
+                .meshes
+                .iter()
+                .map(|&i| model.meshes[i].clone())
+                .collect();
+
+            // Fill in missing materials and restore the textures of overridden ones
+            object.materials.truncate(node.meshes.len());
+            for (k, &i) in node.meshes.iter().enumerate() {
+                let original = model.mesh_material(i);
+                match object.materials.get_mut(k) {
+                    Some(Some(m)) => {
+                        if let Some(original) = original.filter(|o| o.name == m.name) {
+                            m.maps = original.maps.clone();
+                        }
+                    }
+                    Some(None) => {}
+                    None => object.materials.push(original.cloned()),
+                }
+            }
+        }
+
+        scene.update_transforms();
+        Ok(scene)
+    }
+
+    /// Saves the scene to a RON file.
+    ///
+    /// Meshes are stored as references to their model nodes, with paths relative
+    /// to the scene file, and object ids are replaced by positions in the file.
+    /// Meshes not instantiated from a model file cannot be stored and are left out
+    pub fn save(&self, path: &Path) -> Result<(), SceneError> {
+        for (id, o) in self.objects.iter() {
+            if o.source.is_none() && !o.meshes.is_empty() {
+                log::warn!(
+                    "Object {} ({:?}) has no model file, its meshes are not saved",
+                    id,
+                    o.name
+                );
+            }
+        }
+
+        let dir = parent_dir(path);
+        let positions: HashMap<_, _> = self
+            .objects
+            .ids()
+            .enumerate()
+            .map(|(i, id)| (id, i))
+            .collect();
+        let file = SceneFile {
+            objects: self
+                .objects
+                .values()
+                .map(|o| ObjectFile {
+                    name: o.name.clone(),
+                    parent: o.parent.map(|p| positions[&p]),
+                    transform: o.transform,
+                    source: o.source.as_ref().map(|s| ModelSource {
+                        path: relative_path(&s.path, dir),
+                        ..s.clone()
+                    }),
+                    materials: o.materials.clone(),
+                })
+                .collect(),
+            camera: self.camera,
+            lights: self.lights.clone(),
+        };
+
+        let config = ron::ser::PrettyConfig::new();
+        let src = ron::ser::to_string_pretty(&file, config).map_err(SceneError::Serialize)?;
+        fs::write(path, src)?;
+        Ok(())
+    }
+}
+
+/// Directory containing the file at `path`
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// `path` expressed relative to the directory `base`, or as an absolute path
+/// if they do not share a root
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let (path, base) = (absolute_path(path), absolute_path(base));
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        return path;
+    }
+    let mut relative: PathBuf = base.components().skip(common).map(|_| "..").collect();
+    relative.extend(path.components().skip(common));
+    relative
+}
+
+/// Canonical form of `path`, or `path` joined to the working directory if it does not exist
+fn absolute_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path)
+        .or_else(|_| env::current_dir().map(|dir| dir.join(path)))
+        .unwrap_or_else(|_| path.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    /// Loads `src` from a temporary scene file
+    fn load(name: &str, src: &str) -> Result<Scene, SceneError> {
+        let file = format!("natsukashii_{}_{}.ron", name, process::id());
+        let path = env::temp_dir().join(file);
+        fs::write(&path, src).unwrap();
+        let scene = Scene::load(&path);
+        fs::remove_file(&path).unwrap();
+        scene
+    }
+
+    #[test]
+    fn parents_refer_to_file_positions() {
+        let scene = load(
+            "parents",
+            r#"(objects: [(name: "b", parent: Some(1)), (name: "a")])"#,
+        )
+        .unwrap();
+        let (a, b) = (scene.find("a").unwrap(), scene.find("b").unwrap());
+        assert_eq!(scene.object(b).unwrap().parent, Some(a));
+        assert_eq!(scene.object(a).unwrap().parent, None);
+    }
+
+    #[test]
+    fn dangling_parents_are_rejected() {
+        let result = load("dangling", r#"(objects: [(name: "a", parent: Some(1))])"#);
+        assert!(matches!(result, Err(SceneError::InvalidParent(_))));
+    }
+
+    #[test]
+    fn parent_cycles_are_rejected() {
+        let src = r#"(objects: [
+            (name: "a"),
+            (name: "b", parent: Some(2)),
+            (name: "c", parent: Some(1)),
+        ])"#;
+        assert!(matches!(
+            load("cycle", src),
+            Err(SceneError::InvalidParent(_))
+        ));
+        let src = r#"(objects: [(name: "a", parent: Some(0))])"#;
+        assert!(matches!(
+            load("self", src),
+            Err(SceneError::InvalidParent(_))
+        ));
+    }
+
+    #[test]
+    fn light_ranges_are_clamped() {
+        let src = r#"(lights: [(
+            kind: Point(position: (0.0, 1.0, 0.0)),
+            color: (1.0, 1.0, 1.0),
+            intensity: 1.0,
+            range: 0.0,
+        )])"#;
+        let scene = load("lights", src).unwrap();
+        assert_eq!(scene.lights[0].range, 0.0);
+        assert_eq!(scene.lights[0].clamped_range(), crate::light::MIN_RANGE);
+    }
+
+    #[test]
+    fn paths_are_made_relative() {
+        let base = Path::new("/scenes/city");
+        assert_eq!(
+            relative_path(Path::new("/scenes/models/car.obj"), base),
+            Path::new("../models/car.obj")
+        );
+        assert_eq!(
+            relative_path(Path::new("/scenes/city/car.obj"), base),
+            Path::new("car.obj")
+        );
+    }
+}
@@ -0,0 +1,203 @@
+//
+// slot_map.rs
+//
+
+use std::{fmt, iter};
+
+/// Storage handing out stable ids for its values
+///
+/// Removed values leave their slot free for reuse, and every reuse bumps the
+/// slot generation, so ids of removed values never refer to newer ones
+#[derive(Clone, Debug)]
+pub struct SlotMap<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+    len: usize,
+}
+
+/// Id of a value in a SlotMap, valid until the value is removed
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SlotId {
+    index: u32,
+    generation: u32,
+}
+
+#[derive(Clone, Debug)]
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+impl<T> SlotMap<T> {
+    pub fn new() -> Self {
+        SlotMap {
+            slots: vec![],
+            free: vec![],
+            len: 0,
+        }
+    }
+
+    /// Stores `value` in a free slot and returns its id
+    pub fn insert(&mut self, value: T) -> SlotId {
+        self.len += 1;
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.generation += 1;
+                slot.value = Some(value);
+                SlotId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: Some(value),
+                });
+                SlotId {
+                    index: self.slots.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    /// Removes and returns the value of `id`, None if it was already removed
+    pub fn remove(&mut self, id: SlotId) -> Option<T> {
+        let slot = self.slots.get_mut(id.index as usize)?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        let value = slot.value.take()?;
+        self.free.push(id.index);
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub fn get(&self, id: SlotId) -> Option<&T> {
+        self.slots
+            .get(id.index as usize)
+            .filter(|s| s.generation == id.generation)?
+            .value
+            .as_ref()
+    }
+
+    pub fn get_mut(&mut self, id: SlotId) -> Option<&mut T> {
+        self.slots
+            .get_mut(id.index as usize)
+            .filter(|s| s.generation == id.generation)?
+            .value
+            .as_mut()
+    }
+
+    pub fn contains(&self, id: SlotId) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterates over the values along with their ids, in slot order
+    pub fn iter(&self) -> impl Iterator<Item = (SlotId, &T)> + '_ {
+        self.slots.iter().enumerate().filter_map(|(i, slot)| {
+            let id = SlotId {
+                index: i as u32,
+                generation: slot.generation,
+            };
+            slot.value.as_ref().map(|v| (id, v))
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (SlotId, &mut T)> + '_ {
+        self.slots.iter_mut().enumerate().filter_map(|(i, slot)| {
+            let id = SlotId {
+                index: i as u32,
+                generation: slot.generation,
+            };
+            slot.value.as_mut().map(|v| (id, v))
+        })
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = SlotId> + '_ {
+        self.iter().map(|(id, _)| id)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> + '_ {
+        self.iter().map(|(_, v)| v)
+    }
+
+    /// Number of slots, bounding the `SlotId::index` of every stored value
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+}
+
+impl SlotId {
+    /// Position of the slot, unique among the values stored at the same time
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+}
+
+impl<T> Default for SlotMap<T> {
+    fn default() -> Self {
+        SlotMap::new()
+    }
+}
+
+/// Stores the values in order, the nth value getting the nth slot
+impl<T> iter::FromIterator<T> for SlotMap<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(values: I) -> Self {
+        let mut map = SlotMap::new();
+        for value in values {
+            map.insert(value);
+        }
+        map
+    }
+}
+
+impl fmt::Display for SlotId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}v{}", self.index, self.generation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removed_ids_stay_invalid() {
+        let mut map: SlotMap<_> = ["a", "b", "c"].iter().copied().collect();
+        let ids: Vec<_> = map.ids().collect();
+        assert_eq!(map.remove(ids[1]), Some("b"));
+        assert_eq!(map.remove(ids[1]), None);
+
+        // The freed slot is reused under a new generation
+        let d = map.insert("d");
+        assert_eq!(d.index(), ids[1].index());
+        assert_ne!(d, ids[1]);
+        assert_eq!(map.get(ids[1]), None);
+        assert_eq!(map.get(d), Some(&"d"));
+        assert_eq!(map.get(ids[2]), Some(&"c"));
+    }
+
+    #[test]
+    fn iteration_skips_free_slots() {
+        let mut map = SlotMap::new();
+        let a = map.insert(1);
+        let b = map.insert(2);
+        let c = map.insert(3);
+        map.remove(b);
+        *map.get_mut(c).unwrap() += 10;
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.capacity(), 3);
+        assert_eq!(map.iter().collect::<Vec<_>>(), vec![(a, &1), (c, &13)]);
+    }
+}
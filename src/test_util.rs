@@ -0,0 +1,29 @@
+//
+// test_util.rs
+//
+
+use glam::Vec3;
+use std::fmt::Debug;
+
+/// Values compared by `assert_close`
+pub trait Close: Copy + Debug {
+    fn distance(self, other: Self) -> f32;
+}
+
+impl Close for f32 {
+    fn distance(self, other: Self) -> f32 {
+        (self - other).abs()
+    }
+}
+
+impl Close for Vec3 {
+    fn distance(self, other: Self) -> f32 {
+        Vec3::distance(self, other)
+    }
+}
+
+/// Asserts that `a` and `b` are equal up to rounding errors
+#[track_caller]
+pub fn assert_close<T: Close>(a: T, b: T) {
+    assert!(a.distance(b) < 1e-5, "{:?} != {:?}", a, b);
+}
@@ -2,15 +2,25 @@
 // uniform.rs
 //
 
+use super::light::{Light, LightKind};
 use bytemuck::{Pod, Zeroable};
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Vec3, Vec4};
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 
+/// Uniform buffer along with the bind group that binds it,
+/// kept around so the buffer can be updated in place
+pub struct UniformBinding {
+    pub buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
 pub struct ViewProjUniform {
     pub view: Mat4,
     pub proj: Mat4,
+    /// Camera position, w unused
+    pub eye: Vec4,
 }
 
 impl ViewProjUniform {
@@ -19,7 +29,7 @@ impl ViewProjUniform {
             label: None,
             entries: &[wgpu::BindGroupLayoutEntry {
                 binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Uniform,
                     has_dynamic_offset: false,
@@ -70,35 +80,314 @@ impl TransformUniform {
         })
     }
 
-    pub fn create_bind_group(
+    pub fn create_binding(
         &self,
         device: &wgpu::Device,
         layout: &wgpu::BindGroupLayout,
-    ) -> wgpu::BindGroup {
-        device.create_bind_group(&wgpu::BindGroupDescriptor {
+    ) -> UniformBinding {
+        let buffer = self.create_buffer(device);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
             layout,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
-                resource: self.create_buffer(device).as_entire_binding(),
+                resource: buffer.as_entire_binding(),
             }],
-        })
+        });
+        UniformBinding { buffer, bind_group }
+    }
+
+    pub fn write(&self, queue: &wgpu::Queue, binding: &UniformBinding) {
+        queue.write_buffer(&binding.buffer, 0, bytemuck::bytes_of(self));
     }
 }
 
+/// Number of texture maps in the material bind group, see `MaterialMaps`
+pub const MATERIAL_MAPS: usize = 5;
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
 pub struct MaterialUniform {
-    pub albedo: Vec3,
+    pub base_color: Vec3,
+    pub metallic: f32,
+    pub emissive: Vec3,
+    pub roughness: f32,
+    pub specular: Vec3,
+    pub shininess: f32,
+    pub ambient: Vec3,
+    pub dissolve: f32,
+    /// Zero when the material has no normal map
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    /// 0 for metallic-roughness, 1 for Blinn-Phong
+    pub shading: u32,
+    pub _pad: f32,
 }
 
 impl MaterialUniform {
+    /// Layout of the material uniform, the map sampler and the maps following it
+    pub fn layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let mut entries = vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler {
+                    comparison: false,
+                    filtering: true,
+                },
+                count: None,
+            },
+        ];
+        for i in 0..MATERIAL_MAPS {
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: 2 + i as u32,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            });
+        }
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &entries,
+        })
+    }
+
+    pub fn create_buffer(&self, device: &wgpu::Device) -> wgpu::Buffer {
+        device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::bytes_of(self),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+
+    /// Binds the uniform along with `maps` in `MaterialMaps` order
+    pub fn create_binding(
+        &self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        maps: &[&wgpu::TextureView; MATERIAL_MAPS],
+    ) -> UniformBinding {
+        let buffer = self.create_buffer(device);
+        let bind_group = Self::create_bind_group(device, layout, &buffer, sampler, maps);
+        UniformBinding { buffer, bind_group }
+    }
+
+    /// Binds an existing uniform `buffer` along with `maps` in `MaterialMaps` order
+    pub fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+        sampler: &wgpu::Sampler,
+        maps: &[&wgpu::TextureView; MATERIAL_MAPS],
+    ) -> wgpu::BindGroup {
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+        ];
+        for (i, map) in maps.iter().enumerate() {
+            entries.push(wgpu::BindGroupEntry {
+                binding: 2 + i as u32,
+                resource: wgpu::BindingResource::TextureView(map),
+            });
+        }
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &entries,
+        })
+    }
+
+    pub fn write(&self, queue: &wgpu::Queue, binding: &UniformBinding) {
+        queue.write_buffer(&binding.buffer, 0, bytemuck::bytes_of(self));
+    }
+}
+
+/// Number of lights the forward pass shades with, see `res/shaders/inc/lights.glsl`
+pub const MAX_LIGHTS: usize = 16;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+pub struct LightUniform {
+    /// W is the kind of the light
+    pub position: Vec4,
+    /// W is the range
+    pub direction: Vec4,
+    /// Color scaled by intensity
+    pub color: Vec4,
+    /// Cosines of the inner and outer spot angles
+    pub cone: Vec4,
+    /// Projects world positions into the shadow map
+    pub view_proj: Mat4,
+    /// Shadow map layer or -1 without one, bias, slope bias
+    /// and the fraction of the layer the shadow map covers.
+    /// Point lights use six consecutive layers, one per cube face
+    pub shadow: Vec4,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+pub struct LightsUniform {
+    pub lights: [LightUniform; MAX_LIGHTS],
+    pub count: u32,
+    pub _pad: [u32; 3],
+}
+
+impl LightUniform {
+    pub fn new(light: &Light) -> Self {
+        let (kind, position, direction, cone) = match light.kind {
+            LightKind::Directional { direction } => (0.0, Vec3::ZERO, direction, Vec4::ZERO),
+            LightKind::Point { position } => (1.0, position, Vec3::ZERO, Vec4::ZERO),
+            LightKind::Spot {
+                position,
+                direction,
+                inner_angle,
+                outer_angle,
+            } => {
+                let cone = Vec4::new(inner_angle.cos(), outer_angle.cos(), 0.0, 0.0);
+                (2.0, position, direction, cone)
+            }
+        };
+        LightUniform {
+            position: position.extend(kind),
+            direction: direction.extend(light.clamped_range()),
+            color: (light.color * light.intensity).extend(1.0),
+            cone,
+            view_proj: Mat4::IDENTITY,
+            shadow: Vec4::new(-1.0, 0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl LightsUniform {
+    /// Packs the first `MAX_LIGHTS` lights, the rest are ignored
+    pub fn new(lights: &[Light]) -> Self {
+        let mut uniform = LightsUniform::default();
+        for (u, l) in uniform.lights.iter_mut().zip(lights) {
+            *u = LightUniform::new(l);
+        }
+        uniform.count = lights.len().min(MAX_LIGHTS) as u32;
+        uniform
+    }
+
+    /// Layout of the lights uniform, the shadow maps array and their comparison sampler
+    pub fn layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler {
+                        comparison: true,
+                        filtering: true,
+                    },
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    pub fn create_buffer(&self, device: &wgpu::Device) -> wgpu::Buffer {
+        device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::bytes_of(self),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+
+    pub fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+        shadow_maps: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(shadow_maps),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    pub fn write(&self, queue: &wgpu::Queue, binding: &UniformBinding) {
+        queue.write_buffer(&binding.buffer, 0, bytemuck::bytes_of(self));
+    }
+}
+
+/// View of a light rendering one shadow map layer
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+pub struct ShadowUniform {
+    pub view: Mat4,
+    pub proj: Mat4,
+    /// Range of point lights, which store linear distances instead of depth
+    pub range: f32,
+    pub _pad: [f32; 3],
+}
+
+impl ShadowUniform {
     pub fn layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
         device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: None,
             entries: &[wgpu::BindGroupLayoutEntry {
                 binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Uniform,
                     has_dynamic_offset: false,
@@ -117,18 +406,67 @@ impl MaterialUniform {
         })
     }
 
-    pub fn create_bind_group(
+    pub fn create_binding(
         &self,
         device: &wgpu::Device,
         layout: &wgpu::BindGroupLayout,
-    ) -> wgpu::BindGroup {
-        device.create_bind_group(&wgpu::BindGroupDescriptor {
+    ) -> UniformBinding {
+        let buffer = self.create_buffer(device);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
             layout,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
-                resource: self.create_buffer(device).as_entire_binding(),
+                resource: buffer.as_entire_binding(),
             }],
-        })
+        });
+        UniformBinding { buffer, bind_group }
+    }
+
+    pub fn write(&self, queue: &wgpu::Queue, binding: &UniformBinding) {
+        queue.write_buffer(&binding.buffer, 0, bytemuck::bytes_of(self));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::size_of;
+
+    #[test]
+    fn lights_match_std140_layout() {
+        // Four vec4, a mat4 and another vec4 per light, then the count padded to a vec4
+        assert_eq!(size_of::<LightUniform>(), 144);
+        assert_eq!(size_of::<LightsUniform>(), 144 * MAX_LIGHTS + 16);
+    }
+
+    #[test]
+    fn lights_are_packed() {
+        let spot = Light::spot(Vec3::ONE, Vec3::Z, 0.5, 10.0).with_color(Vec3::X, 2.0);
+        let lights = [
+            Light::directional(-Vec3::Y),
+            Light::point(Vec3::Y, 0.0),
+            spot,
+        ];
+        let uniform = LightsUniform::new(&lights);
+        let [directional, point, spot] = [uniform.lights[0], uniform.lights[1], uniform.lights[2]];
+        assert_eq!(uniform.count, 3);
+
+        assert_eq!(directional.position.w, 0.0);
+        assert_eq!(directional.direction.truncate(), -Vec3::Y);
+        assert_eq!(point.position, Vec4::new(0.0, 1.0, 0.0, 1.0));
+        assert_eq!(point.direction.w, crate::light::MIN_RANGE);
+        assert_eq!(spot.position, Vec4::new(1.0, 1.0, 1.0, 2.0));
+        assert_eq!(spot.direction, Vec4::new(0.0, 0.0, 1.0, 10.0));
+        assert_eq!(spot.color, Vec4::new(2.0, 0.0, 0.0, 1.0));
+        assert_eq!(spot.cone.x, (0.5f32 * 0.8).cos());
+        assert_eq!(spot.cone.y, 0.5f32.cos());
+        assert!(uniform.lights[..3].iter().all(|l| l.shadow.x == -1.0));
+    }
+
+    #[test]
+    fn extra_lights_are_ignored() {
+        let lights = vec![Light::directional(Vec3::Y); MAX_LIGHTS + 4];
+        assert_eq!(LightsUniform::new(&lights).count, MAX_LIGHTS as u32);
     }
 }